@@ -0,0 +1,17 @@
+use std::process::Command;
+
+/// Records the git commit the binary was built from, when building from a checkout.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output();
+    if let Ok(output) = output
+        && output.status.success()
+    {
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        println!("cargo:rustc-env=UDS_AUDIO_GIT_HASH={}", hash);
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Named positions within tracks, kept on disk so they survive restarts.
+pub struct Bookmarks {
+    file: Option<PathBuf>,
+    // Seconds into the track by bookmark name, per track.
+    bookmarks: HashMap<String, BTreeMap<String, f64>>,
+}
+
+impl Bookmarks {
+    /// Reads the saved bookmarks from `file`. A missing or unreadable file starts empty.
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let bookmarks = file
+            .as_ref()
+            .and_then(|file| std::fs::read(file).ok())
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+        Bookmarks { file, bookmarks }
+    }
+
+    /// Marks `position` in `path` as `name`, moving any bookmark already called that.
+    pub fn add(&mut self, path: &str, name: &str, position: Duration) {
+        self.bookmarks
+            .entry(crate::resume::key(path))
+            .or_default()
+            .insert(name.to_string(), position.as_secs_f64());
+        self.save();
+    }
+
+    pub fn get(&self, path: &str, name: &str) -> Option<Duration> {
+        let secs = *self.bookmarks.get(&crate::resume::key(path))?.get(name)?;
+        Duration::try_from_secs_f64(secs).ok()
+    }
+
+    /// Some track with a bookmark called `name`, to tell apart a typo from a bookmark in
+    /// another track.
+    pub fn holder(&self, name: &str) -> Option<&str> {
+        self.bookmarks
+            .iter()
+            .find(|(_, bookmarks)| bookmarks.contains_key(name))
+            .map(|(path, _)| path.as_str())
+    }
+
+    /// Every bookmark in `path`, earliest first.
+    pub fn list(&self, path: &str) -> Vec<(String, Duration)> {
+        let Some(bookmarks) = self.bookmarks.get(&crate::resume::key(path)) else {
+            return Vec::new();
+        };
+        let mut list: Vec<(String, Duration)> = bookmarks
+            .iter()
+            .filter_map(|(name, secs)| {
+                Some((name.clone(), Duration::try_from_secs_f64(*secs).ok()?))
+            })
+            .collect();
+        list.sort_by_key(|(_, position)| *position);
+        list
+    }
+
+    fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let saved = file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(file, serde_json::to_vec(&self.bookmarks)?));
+        if let Err(e) = saved {
+            tracing::error!("Failed to save bookmarks to {}: {}", file.display(), e);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{DaemonArgs, LogLevel, Normalize, RepeatMode};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Defaults read from a TOML file, for settings that would otherwise need passing as flags
+/// on every launch.
+///
+/// Keys are spelled like the flags they stand in for. A flag or environment variable always
+/// wins over the file, and the file wins over the built-in defaults.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    // Where the file was read from; `None` when there wasn't one.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    pub socket: Option<PathBuf>,
+    pub token: Option<String>,
+    volume: Option<f32>,
+    fade_ms: Option<u64>,
+    crossfade_ms: Option<u64>,
+    gap_ms: Option<u64>,
+    prefetch_ms: Option<u64>,
+    autoplay: Option<String>,
+    repeat: Option<RepeatMode>,
+    notify: Option<bool>,
+    history_log: Option<PathBuf>,
+    normalize: Option<Normalize>,
+    mono: Option<bool>,
+    crossfeed: Option<bool>,
+    limiter: Option<bool>,
+    eq: Option<Vec<f32>>,
+    trim_silence: Option<bool>,
+    trim_threshold_db: Option<f32>,
+    trim_max_ms: Option<u64>,
+    device: Option<String>,
+    request_timeout: Option<u64>,
+    max_queue: Option<usize>,
+    log_level: Option<LogLevel>,
+    log_file: Option<PathBuf>,
+    listen: Option<String>,
+    mpris: Option<bool>,
+}
+
+/// `$XDG_CONFIG_HOME/uds-audio/config.toml`, falling back to `~/.config` like `state_dir`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("uds-audio").join("config.toml"))
+}
+
+/// Reads the config file at `path`, or at the default path when none is given. Only a file
+/// that was asked for by name has to exist; a missing default just means no file.
+pub fn load(path: Option<&Path>) -> Result<Config, String> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+            return Ok(Config::default());
+        }
+        Err(e) => return Err(format!("Failed to read config {}: {}", path.display(), e)),
+    };
+    let mut config: Config = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+    config.path = Some(path);
+    Ok(config)
+}
+
+impl Config {
+    /// Fills in the daemon options that weren't given on the command line.
+    pub fn apply(self, options: &mut DaemonArgs, matches: &ArgMatches) {
+        // Each key has the same name as the option it fills in; `into` wraps the value in
+        // `Some` for options without a default.
+        macro_rules! fill {
+            ($($option:ident),*) => {$(
+                if let Some(value) = self.$option
+                    && !given(matches, stringify!($option))
+                {
+                    options.$option = value.into();
+                }
+            )*};
+        }
+        fill!(
+            volume,
+            fade_ms,
+            crossfade_ms,
+            gap_ms,
+            prefetch_ms,
+            autoplay,
+            repeat,
+            notify,
+            history_log,
+            normalize,
+            mono,
+            crossfeed,
+            limiter,
+            eq,
+            trim_silence,
+            trim_threshold_db,
+            trim_max_ms,
+            device,
+            request_timeout,
+            max_queue,
+            log_level,
+            log_file,
+            listen,
+            mpris
+        );
+    }
+}
+
+/// Whether the option `id` was set with a flag or through the environment, rather than left
+/// at its default.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
@@ -0,0 +1,58 @@
+use rodio::DeviceTrait;
+use rodio::cpal::traits::HostTrait;
+
+/// Names of the output devices on the default host, in the order the host lists them.
+pub fn output_device_names() -> Vec<String> {
+    let host = rodio::cpal::default_host();
+    let Ok(devices) = host.output_devices() else {
+        return Vec::new();
+    };
+    devices.filter_map(|device| device.name().ok()).collect()
+}
+
+pub fn default_output_device_name() -> Option<String> {
+    let host = rodio::cpal::default_host();
+    host.default_output_device()?.name().ok()
+}
+
+/// Finds the output device called `name`. An exact match wins; otherwise the first device
+/// whose name contains `name`, ignoring case, so long ALSA names don't have to be typed out.
+pub fn find_output_device(name: &str) -> Option<rodio::Device> {
+    let host = rodio::cpal::default_host();
+    let mut devices: Vec<rodio::Device> = host.output_devices().ok()?.collect();
+    let named = |device: &rodio::Device| device.name().unwrap_or_default();
+
+    if let Some(index) = devices.iter().position(|device| named(device) == name) {
+        return Some(devices.swap_remove(index));
+    }
+    let needle = name.to_lowercase();
+    devices
+        .into_iter()
+        .find(|device| named(device).to_lowercase().contains(&needle))
+}
+
+/// Opens the requested device, falling back to the default one with a warning when it can't
+/// be found or opened. Also returns the name of the device that ended up being used.
+pub fn open_output_stream(name: Option<&str>) -> (rodio::OutputStream, Option<String>) {
+    if let Some(name) = name {
+        match find_output_device(name) {
+            Some(device) => {
+                let opened = device.name().ok();
+                match rodio::OutputStreamBuilder::from_device(device)
+                    .and_then(|builder| builder.open_stream_or_fallback())
+                {
+                    Ok(stream) => return (stream, opened),
+                    Err(e) => tracing::warn!(
+                        "Could not open output device {}: {}; using the default",
+                        name,
+                        e
+                    ),
+                }
+            }
+            None => tracing::warn!("Output device {} not found; using the default", name),
+        }
+    }
+    let stream =
+        rodio::OutputStreamBuilder::open_default_stream().expect("open default audio stream");
+    (stream, default_output_device_name())
+}
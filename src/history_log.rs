@@ -0,0 +1,66 @@
+use crate::TrackInfo;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// `--history-log`: one JSON line per track that starts playing, appended to a file.
+///
+/// Lines are handed to a background task that does the writing, so a slow disk never holds
+/// up the player.
+pub struct HistoryLog {
+    path: PathBuf,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl HistoryLog {
+    /// Opens `path` for appending, creating it if needed, so earlier runs' lines are kept.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let (lines, mut received) = mpsc::unbounded_channel::<String>();
+        let path = path.to_path_buf();
+        let log_path = path.clone();
+        tokio::spawn(async move {
+            let mut writer = BufWriter::new(tokio::fs::File::from_std(file));
+            while let Some(line) = received.recv().await {
+                // Flushed per line so the file is complete even if the daemon is killed.
+                let written = match writer.write_all(line.as_bytes()).await {
+                    Ok(()) => writer.flush().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = written {
+                    tracing::warn!("Failed to write history log {}: {}", path.display(), e);
+                }
+            }
+        });
+        Ok(HistoryLog {
+            path: log_path,
+            lines,
+        })
+    }
+
+    /// Empties the file. The writer appends, so later lines start again from the top.
+    pub fn truncate(&mut self) -> std::io::Result<()> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)?
+            .set_len(0)?;
+        Ok(())
+    }
+
+    /// Records that `track` started playing.
+    pub fn track_started(&mut self, track: &TrackInfo) {
+        let line = json!({
+            "timestamp": crate::unix_secs(SystemTime::now()),
+            "path": track.path,
+            "title": track.title,
+            "artist": track.artist,
+            "duration_secs": track.duration.map(|duration| duration.as_secs_f64())
+        });
+        let _ = self.lines.send(format!("{}\n", line));
+    }
+}
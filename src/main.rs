@@ -1,55 +1,409 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
 use colored::Colorize;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rodio::Sink;
 use rodio::Source;
-use rodio::{Decoder, Sink};
+use rustyline::error::ReadlineError;
 use serde::Deserialize;
 use serde_json::Value;
 use serde_json::json;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufReader, IsTerminal};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncBufRead;
+use tokio::io::AsyncBufReadExt;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use tokio::net::UnixStream;
-use tracing::info;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
 
-const SOCKET_PATH: &str = "/tmp/sound.sock";
+mod bookmarks;
+mod config;
+mod devices;
+mod history_log;
+#[cfg(feature = "mpris")]
+mod mpris;
+mod notify;
+mod playlist;
+mod response;
+mod resume;
+mod socket;
+mod sources;
+mod stream;
+mod tags;
 
-#[derive(Debug, Clone)]
-enum Actions {
-    Play,
-    Pause,
-    Resume,
-    Clear,
-    Queue,
-    Skip,
-    Current,
+use response::{PlayerError, PlayerResponse, PlayerResult};
+use socket::Endpoint;
+use sources::{
+    Balance, Cancellable, ClipGuard, ClipMeter, Crossfade, CrossfadeBus, Crossfeed, EQ_BANDS,
+    EqGains, Equalizer, Gap, LevelMeter, Meter, Mono, Prefetch, Prefetcher, TimeStretch,
+    TrimSilence, Trimmed,
+};
+
+const DEFAULT_SOCKET_PATH: &str = "/tmp/sound.sock";
+const SOCKET_FILE_NAME: &str = "uds-audio.sock";
+const SAVED_QUEUE_FILE_NAME: &str = "queue.m3u";
+const POSITIONS_FILE_NAME: &str = "positions.json";
+const BOOKMARKS_FILE_NAME: &str = "bookmarks.json";
+const PLAYLISTS_DIR_NAME: &str = "playlists";
+const REPL_HISTORY_FILE_NAME: &str = "repl_history";
+const MAX_VOLUME: f32 = 2.0;
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+// The most any equalizer band can boost or cut, in dB.
+const MAX_EQ_GAIN: f32 = 12.0;
+// The longest request line the daemon reads; a client sending more is answered and dropped.
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+const SYNC_INTERVAL: Duration = Duration::from_millis(200);
+// The longest the client waits between two attempts to connect, however far it has backed off.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+// How long the client gives the daemon to answer the `ping` it sends before each command.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+const FADE_STEP: Duration = Duration::from_millis(10);
+const HISTORY_LIMIT: usize = 50;
+// The longest prefetch `tune` accepts; a minute of decoded audio is tens of megabytes.
+const MAX_PREFETCH: Duration = Duration::from_secs(60);
+const EVENT_CAPACITY: usize = 64;
+// Levels older than this are from before playback stopped and read as silence.
+const LEVELS_STALE: Duration = Duration::from_millis(250);
+// How soon the log may say again that the output is clipping.
+const CLIP_WARN_INTERVAL: Duration = Duration::from_secs(60);
+/// File extensions this build can decode, and the codec behind each. Mirrors the decoders
+/// enabled by rodio's default features (all backed by symphonia).
+const SUPPORTED_FORMATS: [(&str, &str); 9] = [
+    ("mp3", "MP3"),
+    ("flac", "FLAC"),
+    ("wav", "PCM (WAV)"),
+    ("ogg", "Vorbis"),
+    ("oga", "Vorbis"),
+    ("m4a", "AAC (MP4)"),
+    ("mp4", "AAC (MP4)"),
+    ("m4b", "AAC (MP4)"),
+    ("aac", "AAC (ADTS)"),
+];
+
+type TrackSource = Box<dyn Source + Send>;
+
+// Set by `--quiet`: success messages are left out and only errors are printed.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Declares every action the daemon understands, once: the variant, its name on the wire,
+/// and the fields it can't do without. The daemon parses requests with it and the CLI builds
+/// them from it, so a name is never spelled twice.
+macro_rules! actions {
+    ($($action:ident => $name:literal $([$($field:literal: $kind:ident),*])?,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Actions {
+            $($action,)*
+        }
+
+        impl Actions {
+            #[cfg(test)]
+            const ALL: &[Actions] = &[$(Actions::$action,)*];
+
+            fn from_str(s: &str) -> Option<Self> {
+                match s {
+                    $($name => Some(Actions::$action),)*
+                    _ => None,
+                }
+            }
+
+            fn name(self) -> &'static str {
+                match self {
+                    $(Actions::$action => $name,)*
+                }
+            }
+
+            /// Fields the action can't do without.
+            fn required_fields(self) -> &'static [(&'static str, FieldKind)] {
+                match self {
+                    $(Actions::$action => &[$($(($field, FieldKind::$kind)),*)?],)*
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    Play => "play" ["track": Strings],
+    Pause => "pause",
+    Resume => "resume",
+    Clear => "clear",
+    Queue => "queue" ["track": Strings],
+    Skip => "skip",
+    Current => "current",
+    Volume => "volume",
+    Stop => "stop",
+    Status => "status",
+    List => "list",
+    Remove => "remove" ["index": Index],
+    Repeat => "repeat" ["mode": String],
+    Shuffle => "shuffle",
+    Mute => "mute",
+    Unmute => "unmute",
+    Prev => "prev",
+    Subscribe => "subscribe",
+    Speed => "speed" ["factor": Number],
+    Jump => "jump" ["index": Index],
+    Version => "version",
+    Move => "move" ["from": Index, "to": Index],
+    Ping => "ping",
+    Shutdown => "shutdown",
+    Toggle => "toggle",
+    Levels => "levels",
+    Balance => "balance" ["value": Number],
+    Dedup => "dedup",
+    Restart => "restart",
+    Sort => "sort" ["key": String],
+    Forget => "forget" ["track": String],
+    Session => "session",
+    Config => "config",
+    Seek => "seek",
+    Insert => "insert" ["index": Index, "track": String],
+    BookmarkAdd => "bookmark_add" ["name": String],
+    BookmarkList => "bookmark_list",
+    BookmarkJump => "bookmark_jump" ["name": String],
+    FadeStop => "fadestop" ["seconds": Number],
+    Contains => "contains" ["track": String],
+    Rename => "rename" ["index": Index, "name": String],
+    History => "history",
+    HistoryClear => "history_clear",
+    Tune => "tune",
+    Eq => "eq",
+    EqReset => "eq_reset",
+    Swap => "swap" ["a": Index, "b": Index],
+    Save => "save" ["path": String],
+    PlaylistSave => "playlist_save" ["name": String],
+    PlaylistLoad => "playlist_load" ["name": String],
+    PlaylistList => "playlist_list",
 }
 
 impl Actions {
+    /// A request for this action, made from the fields it takes.
+    fn request(self, mut fields: Value) -> Value {
+        fields["action"] = json!(self.name());
+        fields
+    }
+}
+
+impl<'de> Deserialize<'de> for Actions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Actions::from_str(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown action '{}'", name)))
+    }
+}
+
+/// Where newly loaded tracks go.
+#[derive(Debug, Clone, Copy)]
+enum Placement {
+    // Replace the queue and start playing.
+    Play,
+    // Replace the queue but stay paused at the start of its first track.
+    Cue,
+    // Right after the current track.
+    Next,
+    // At the end of the queue.
+    End,
+    // At this queue index, clamped to the end. Never before the current track.
+    At(usize),
+}
+
+#[derive(Debug, Clone)]
+struct TrackInfo {
+    // Unique per queue entry; set when the track enters the queue.
+    id: u64,
+    // Display name: "Artist – Title" when tagged, otherwise the file name.
+    name: String,
+    path: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    // Unknown for live streams.
+    duration: Option<Duration>,
+    // From the file extension, or a stream's content type; `None` if neither says.
+    codec: Option<&'static str>,
+    replay_gain: tags::ReplayGain,
+}
+
+impl TrackInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name.clone(),
+            "path": self.path.clone(),
+            "title": self.title.clone(),
+            "artist": self.artist.clone(),
+            "album": self.album.clone(),
+            "duration_secs": self.duration.map(|d| d.as_secs_f64()),
+            "duration": self.duration.map(format_duration)
+        })
+    }
+}
+
+/// Which ReplayGain value evens out loudness between tracks.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Normalize {
+    Off,
+    // Every track at the same loudness; falls back to the album gain.
+    Track,
+    // Keeps the dynamics between tracks of an album; falls back to the track gain.
+    Album,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+impl Normalize {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Normalize::Off => "off",
+            Normalize::Track => "track",
+            Normalize::Album => "album",
+        }
+    }
+}
+
+impl RepeatMode {
     fn from_str(s: &str) -> Option<Self> {
         match s {
-            "play" => Some(Actions::Play),
-            "pause" => Some(Actions::Pause),
-            "resume" => Some(Actions::Resume),
-            "clear" => Some(Actions::Clear),
-            "queue" => Some(Actions::Queue),
-            "skip" => Some(Actions::Skip),
-            "current" => Some(Actions::Current),
+            "off" => Some(RepeatMode::Off),
+            "one" => Some(RepeatMode::One),
+            "all" => Some(RepeatMode::All),
             _ => None,
         }
     }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "all",
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-struct TrackInfo {
-    name: String,
-    duration: usize,
+/// A source that has been appended to the sink, tagged with the queue entry it plays.
+struct Slot {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    // Channels and sample rate; tracks only crossfade into ones that match.
+    format: (rodio::ChannelCount, rodio::SampleRate),
+    ahead: Arc<dyn Prefetcher>,
+    prefetching: bool,
+    // How much silence `--trim-silence` cut from the track; `None` when it's off.
+    trimmed: Option<Arc<Trimmed>>,
+}
+
+impl Slot {
+    fn is_live(&self) -> bool {
+        !self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A pause or resume fade in progress.
+#[derive(Clone, Copy)]
+struct Ramp {
+    // The fade's number, which it checks at every step to know it's still wanted.
+    id: u64,
+    // The sink volume it started from.
+    from: f32,
+    // Heading down to pause, rather than back up to the user's volume.
+    pausing: bool,
 }
+
 struct Player {
-    _output_stream: rodio::OutputStream,
     sink: Sink,
+    // Channels and sample rate the output device was opened with; the mixer resamples
+    // every track to this.
+    output_format: (rodio::ChannelCount, rodio::SampleRate),
     queue: Vec<TrackInfo>,
+    // Every source still in the sink, in playback order. Cancelled slots are waiting for
+    // playback to reach them so rodio can drop them.
+    sources: VecDeque<Slot>,
+    repeat: RepeatMode,
+    // Extra plays left for the queue entry started with `play --repeat`, by id.
+    loops: Option<(u64, usize)>,
+    max_queue: usize,
+    positions: resume::Positions,
+    bookmarks: bookmarks::Bookmarks,
+    next_id: u64,
+    // Tracks that finished or were skipped, with when they did, most recent last.
+    history: VecDeque<(TrackInfo, SystemTime)>,
+    // How many tracks have left the front of the queue since it was last started afresh, so
+    // the current one is number `played + 1`. Under `repeat all` it counts from when the
+    // mode was switched on, and wraps with the cycle.
+    played: usize,
+    // The level the user asked for. While muted the sink sits at zero and this is what
+    // unmuting restores.
+    volume: f32,
+    muted: bool,
+    // Set by `stop`, so a rewound track reads as stopped rather than paused.
+    stopped: bool,
+    // The `fadestop` in progress, if any, as its number and the id of the track it's fading.
+    fading: Option<(u64, u64)>,
+    ramping: Option<Ramp>,
+    fades: u64,
+    speed: f32,
+    preserve_pitch: bool,
+    // Tempo ratio read by every appended source's time stretcher. It stays at 1.0 unless
+    // pitch is being preserved, in which case the sink itself runs at normal speed.
+    stretch: Arc<AtomicU32>,
+    // Left/right balance read by every appended source, from -1.0 (left) to 1.0 (right).
+    balance: Arc<AtomicU32>,
+    // Equalizer gains read by every appended source.
+    eq: Arc<EqGains>,
+    fade: Duration,
+    crossfade: Duration,
+    // `--gap-ms`: silence played after every track. Never set along with a crossfade.
+    gap: Duration,
+    crossfade_bus: Arc<CrossfadeBus>,
+    // How long before the current track ends the next one starts decoding, and how much of
+    // it is decoded then.
+    prefetch: Duration,
+    meter: Arc<LevelMeter>,
+    clip: Arc<ClipMeter>,
+    // `--limiter`: samples pushed over full scale are softened instead of clipping hard.
+    limiter: bool,
+    // When the log last warned about clipping.
+    clip_warned: Option<Instant>,
+    normalize: Normalize,
+    // `--mono`: every track is summed to one channel, and the balance is ignored.
+    mono: bool,
+    // `--crossfeed`: stereo tracks bleed a little into the other channel, for headphones.
+    crossfeed: bool,
+    // `--trim-silence`: the level below which a frame counts as silent, and the most cut
+    // from either end of a track.
+    trim: Option<(f32, Duration)>,
+    notify: bool,
+    history_log: Option<history_log::HistoryLog>,
+    // Set when a track becomes the current one, and cleared once `--history-log` has
+    // recorded it playing.
+    log_pending: bool,
+    // How the daemon was started, resolved from flags, environment, and defaults.
+    config: Value,
+    // Subscribers' feed, and the state they were last told about.
+    events: broadcast::Sender<Value>,
+    announced: Option<u64>,
+    announced_queue: Vec<u64>,
+    announced_paused: bool,
 }
 
 #[derive(Deserialize)]
@@ -59,382 +413,5299 @@ struct Response {
 }
 
 impl Player {
-    pub fn new() -> Self {
-        let stream_handle =
-            rodio::OutputStreamBuilder::open_default_stream().expect("open default audio stream");
-        let sink = rodio::Sink::connect_new(&stream_handle.mixer());
+    /// Plays into `mixer`, which feeds an output opened with `output_format`.
+    pub fn new(
+        mixer: &rodio::mixer::Mixer,
+        output_format: (rodio::ChannelCount, rodio::SampleRate),
+        options: &DaemonArgs,
+        config: Value,
+    ) -> Self {
+        let sink = rodio::Sink::connect_new(mixer);
+        let eq = Arc::new(EqGains::default());
+        match options.eq.as_deref() {
+            None => {}
+            Some(&[low, mid, high]) if [low, mid, high].iter().all(|gain| gain.is_finite()) => {
+                eq.set([low, mid, high].map(|gain| gain.clamp(-MAX_EQ_GAIN, MAX_EQ_GAIN)));
+            }
+            Some(gains) => warn!("Invalid EQ {:?}, starting flat", gains),
+        }
+        let volume = match options.volume {
+            volume if volume >= 0.0 => volume.min(MAX_VOLUME),
+            volume => {
+                warn!("Invalid volume {}, starting at 1.0", volume);
+                1.0
+            }
+        };
+        sink.set_volume(volume);
+        // A config file can set both, which the command line doesn't allow.
+        let gap = match (options.gap_ms, options.crossfade_ms) {
+            (0, _) => Duration::ZERO,
+            (_, 0) => Duration::from_millis(options.gap_ms),
+            (gap, _) => {
+                warn!(
+                    "--gap-ms {} can't be used with a crossfade, ignoring it",
+                    gap
+                );
+                Duration::ZERO
+            }
+        };
+        let clip = Arc::new(ClipMeter::default());
+        clip.set_gain(volume);
         Player {
-            _output_stream: stream_handle,
-            sink: sink,
+            sink,
+            output_format,
             queue: Vec::new(),
+            sources: VecDeque::new(),
+            repeat: options.repeat,
+            loops: None,
+            max_queue: options.max_queue,
+            positions: resume::Positions::load(
+                state_dir().map(|dir| dir.join(POSITIONS_FILE_NAME)),
+            ),
+            bookmarks: bookmarks::Bookmarks::load(
+                state_dir().map(|dir| dir.join(BOOKMARKS_FILE_NAME)),
+            ),
+            next_id: 0,
+            history: VecDeque::new(),
+            played: 0,
+            volume,
+            muted: false,
+            stopped: false,
+            fading: None,
+            ramping: None,
+            fades: 0,
+            speed: 1.0,
+            preserve_pitch: false,
+            stretch: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            balance: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+            eq,
+            fade: Duration::from_millis(options.fade_ms),
+            crossfade: Duration::from_millis(options.crossfade_ms),
+            gap,
+            crossfade_bus: Arc::new(CrossfadeBus::default()),
+            prefetch: Duration::from_millis(options.prefetch_ms),
+            meter: Arc::new(LevelMeter::default()),
+            clip,
+            limiter: options.limiter,
+            clip_warned: None,
+            normalize: options.normalize,
+            mono: options.mono,
+            crossfeed: options.crossfeed,
+            trim: options.trim_silence.then(|| {
+                (
+                    10f32.powf(options.trim_threshold_db / 20.0),
+                    Duration::from_millis(options.trim_max_ms),
+                )
+            }),
+            notify: options.notify,
+            history_log: options.history_log.as_deref().and_then(|path| {
+                match history_log::HistoryLog::open(path) {
+                    Ok(log) => Some(log),
+                    Err(e) => {
+                        error!("Failed to open history log {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            }),
+            config,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            log_pending: false,
+            announced: None,
+            announced_queue: Vec::new(),
+            announced_paused: false,
         }
     }
 
-    pub fn push_to_queue(
-        &mut self,
-        source: impl Source + Send + 'static,
-        metadata: TrackInfo,
-    ) -> Value {
-        self.sink.append(source);
-        self.queue.push(metadata.clone());
-        return json!({
-            "status": true,
-            "message": format!("{} was successfully added to the queue", &metadata.name)
-        });
+    /// Advances the queue past tracks rodio has finished playing.
+    ///
+    /// The sink holds one source per slot, so once its length falls below ours the difference
+    /// is exactly the number of sources that played through (or were cancelled). Each live one
+    /// was the current track, which moves the queue on according to the repeat mode.
+    pub fn sync_queue(&mut self) {
+        self.sync_queue_with(None, true);
     }
 
-    pub fn clear_queue(&mut self) -> Value {
-        self.sink.clear();
-        self.queue.clear();
-        return json!({
-            "status": true,
-            "message": "Queue was successfully cleared"
-        });
+    /// Like `sync_queue`, but hands the sink `decoded` if it's the track due next. Without
+    /// `open`, a track that still has to be opened is returned instead, so the caller can open
+    /// it without holding the player and come back with it.
+    fn sync_queue_with(
+        &mut self,
+        decoded: Option<(u64, TrackSource)>,
+        open: bool,
+    ) -> Option<TrackInfo> {
+        let finished = self.sources.len().saturating_sub(self.sink.len());
+        let played: Vec<Slot> = self
+            .sources
+            .drain(..finished)
+            .filter(|slot| slot.is_live())
+            .collect();
+        for slot in &played {
+            self.report_trim(slot);
+        }
+        self.report_clipping();
+        let played = played.len();
+        self.update_crossfade();
+        for _ in 0..played {
+            self.advance();
+        }
+        let due = self.reconcile_with(decoded, open);
+        self.prefetch_next();
+        self.publish_changes();
+        // Only playing through the last track counts. Clearing empties the sink without
+        // anything finishing, so it never shows up in `played`.
+        if played > 0 && self.queue.is_empty() {
+            info!("Queue finished, now idle");
+            self.emit(json!({"event": "queue_finished"}));
+        }
+        due
     }
 
-    pub fn pause(&mut self) -> Value {
-        if self.sink.len() == 0 {
-            return json!({
-                "status": false,
-                "message": "Nothing is being played to pause"
-            });
+    /// Logs how much silence was cut from a track that has finished playing, if any.
+    fn report_trim(&self, slot: &Slot) {
+        let Some(trimmed) = &slot.trimmed else {
+            return;
+        };
+        let (start, end) = (trimmed.start(), trimmed.end());
+        if start.is_zero() && end.is_zero() {
+            return;
         }
+        let Some(track) = self.queue.iter().find(|track| track.id == slot.id) else {
+            return;
+        };
+        info!(
+            "Trimmed {} ms of leading and {} ms of trailing silence from {}",
+            start.as_millis(),
+            end.as_millis(),
+            track.name
+        );
+    }
 
-        if self.sink.is_paused() {
-            return json!({
-                "status": true,
-                "message": "Already paused"
-            });
-        } else {
-            self.sink.pause();
-            return json!({
-                "status": true,
-                "message": "Paused successfully"
-            });
+    /// Warns when the output has recently gone over full scale, at most once a minute so a
+    /// loud track doesn't flood the log.
+    fn report_clipping(&mut self) {
+        let clipped = self.clip.clipped();
+        if clipped == 0
+            || self
+                .clip_warned
+                .is_some_and(|warned| warned.elapsed() < CLIP_WARN_INTERVAL)
+        {
+            return;
+        }
+        self.clip_warned = Some(Instant::now());
+        match self.limiter {
+            true => warn!(
+                "Output is over full scale ({} samples in the last second), softened by the limiter",
+                clipped
+            ),
+            false => warn!(
+                "Output is clipping ({} samples in the last second); lower the volume, EQ, or normalization gain, or start the daemon with --limiter",
+                clipped
+            ),
         }
     }
 
-    pub fn resume(&mut self) -> Value {
-        if self.sink.len() == 0 {
-            return json!({
-                "status": false,
-                "message": "Nothing to resume"
-            });
+    pub fn subscribe(&self) -> broadcast::Receiver<Value> {
+        self.events.subscribe()
+    }
+
+    /// Sends an event to every subscriber. Nobody listening is not an error.
+    fn emit(&self, event: Value) {
+        let _ = self.events.send(event);
+    }
+
+    /// Tells subscribers (and the desktop, with `--notify`) about whatever changed since the
+    /// last call: a new current track, a different queue, or a pause/resume.
+    pub fn publish_changes(&mut self) {
+        let current = self.queue.first();
+        let id = current.map(|track| track.id);
+        if id != self.announced {
+            self.announced = id;
+            self.log_pending = current.is_some();
+            if let Some(track) = current {
+                info!("Started {}", track.name);
+                self.emit(json!({"event": "track_started", "track": track.to_json()}));
+                if self.notify {
+                    notify::track_started(track);
+                }
+            }
         }
 
-        if self.sink.is_paused() {
-            self.sink.play();
-            return json!({
-                "status": true,
-                "message": "Resumed successfully"
-            });
-        } else {
-            return json!({
-                "status": true,
-                "message": "Already playing"
-            });
+        // Only a track that's actually playing counts, so one restored paused at startup
+        // isn't logged until it's resumed, and pausing and resuming isn't another play.
+        if self.log_pending
+            && let Some(log) = &mut self.history_log
+            && let Some(track) = self.queue.first()
+            && !self.sink.is_paused()
+        {
+            log.track_started(track);
+            self.log_pending = false;
         }
-    }
 
-    pub fn play(&mut self, source: impl Source + Send + 'static, metadata: TrackInfo) -> Value {
-        if !self.sink.empty() && !self.sink.is_paused() {
-            return json!({
-                "status": false,
-                "message": "Already playing"
-            });
-        } else {
-            self.sink.clear();
-            self.queue.clear();
-            self.sink.append(source);
-            self.sink.play();
+        let queue: Vec<u64> = self.queue.iter().map(|track| track.id).collect();
+        if queue != self.announced_queue {
+            self.announced_queue = queue;
+            self.emit(json!({"event": "queue_changed", "queue_length": self.queue.len()}));
+        }
 
-            self.queue.push(metadata.clone());
-            return json!({
-                "status": true,
-                "message": format!("Now playing {}", metadata.name)
-            });
+        let paused = !self.queue.is_empty() && self.sink.is_paused();
+        if paused != self.announced_paused {
+            self.announced_paused = paused;
+            self.emit(json!({"event": if paused { "paused" } else { "resumed" }}));
         }
     }
 
-    pub fn skip(&mut self) -> Value {
-        if self.queue.is_empty() && self.sink.len() == 0 {
-            return json!({
-                "status": false,
-                "message": "Nothing to skip"
-            });
-        } else {
-            if !self.queue.is_empty() {
-                let skipped = self.queue.remove(0);
-                self.sink.skip_one();
+    /// Reports that `track` stopped being the current track, and makes sure whatever plays
+    /// next is announced even if it is the same queue entry again.
+    fn finished(&mut self, track: &TrackInfo, reason: &str) {
+        info!("Finished {} ({})", track.name, reason);
+        // Anything but playing to the end leaves the sink on this track, so its position is
+        // still where the listener left it.
+        match reason {
+            "finished" => {
+                self.positions.forget(&track.path);
+            }
+            _ => self
+                .positions
+                .set(&track.path, self.position(), track.duration),
+        }
+        self.emit(json!({"event": "track_finished", "track": track.to_json(), "reason": reason}));
+        self.announced = None;
+    }
 
-                return json!({
-                    "status": true,
-                    "message": format!("Skipped {}", skipped.name)
-                });
-            } else {
-                return json!({
-                    "status": false,
-                    "message": "Queue is empty"
-                });
+    /// Moves the queue on once the current track has played to the end.
+    fn advance(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        let track = self.queue[0].clone();
+        self.finished(&track, "finished");
+        if let Some((_, left)) = self.loops.as_mut().filter(|(id, _)| *id == track.id)
+            && *left > 0
+        {
+            *left -= 1;
+            return;
+        }
+        match self.repeat {
+            RepeatMode::Off => {
+                let finished = self.queue.remove(0);
+                self.remember(finished);
+            }
+            RepeatMode::One => {}
+            RepeatMode::All => {
+                let finished = self.queue.remove(0);
+                self.remember(finished.clone());
+                self.queue.push(finished);
             }
         }
     }
 
-    pub fn current(&mut self) -> Value {
-        if self.queue.is_empty() && self.sink.len() == 0 {
-            return json!({
-                "status": false,
-                "message": "Nothing is being played"
-            });
-        } else {
-            let current_track = self.queue.first().unwrap();
+    /// Records a track that finished or was skipped, dropping the oldest past the limit.
+    fn remember(&mut self, track: TrackInfo) {
+        self.played += 1;
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back((track, SystemTime::now()));
+    }
 
-            return json!({
-                "status": true,
-                "message": format!("Currently playing {}", current_track.name),
-                "track": current_track.name.clone(),
-                "queue_length": self.queue.len()
-            });
+    /// The track that plays once the current one ends.
+    fn next_track(&self) -> Option<&TrackInfo> {
+        if self.loops_left() > 0 {
+            return self.queue.first();
+        }
+        match self.repeat {
+            RepeatMode::Off => self.queue.get(1),
+            RepeatMode::One => self.queue.first(),
+            RepeatMode::All => self.queue.get(1).or(self.queue.first()),
         }
     }
-}
 
-#[derive(Deserialize, Debug)]
-struct Command {
-    action: String,
-    track: Option<String>,
-}
+    /// Plays the current track `times` times in total before the queue moves on.
+    pub fn repeat_current(&mut self, times: usize) {
+        self.loops = self
+            .queue
+            .first()
+            .map(|track| (track.id, times.saturating_sub(1)));
+        self.reconcile(None);
+    }
 
-#[derive(Parser)]
-#[command(name = "socket_app")]
-struct Args {
-    #[command(subcommand)]
-    command: Commands,
-}
+    /// How many more times the current track plays after this time through.
+    fn loops_left(&self) -> usize {
+        match (self.loops, self.queue.first()) {
+            (Some((id, left)), Some(current)) if current.id == id => left,
+            _ => 0,
+        }
+    }
 
-#[derive(Subcommand)]
-enum Commands {
-    Play { track: String },
-    Pause,
-    Resume,
-    Daemon,
-    Queue { track: String },
-    Clear,
-    Skip,
-    Current,
-}
+    /// Brings the sink in line with the queue.
+    ///
+    /// The sink only ever holds the current track followed by the one that plays after it, so
+    /// transitions stay gapless while anything further down the queue is just metadata. Any
+    /// edit to the queue therefore costs at most one re-decode, and `decoded` lets callers
+    /// hand over a source they already opened for entry `id`.
+    fn reconcile(&mut self, decoded: Option<(u64, TrackSource)>) {
+        self.reconcile_with(decoded, true);
+    }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+    /// Does the work of `reconcile`. Without `open`, it stops at the first track it would have
+    /// to open and returns that track instead.
+    fn reconcile_with(
+        &mut self,
+        mut decoded: Option<(u64, TrackSource)>,
+        open: bool,
+    ) -> Option<TrackInfo> {
+        let is_decoded = |decoded: &Option<(u64, TrackSource)>, track: &TrackInfo| {
+            decoded.as_ref().is_some_and(|(id, _)| *id == track.id)
+        };
+        loop {
+            let Some(current) = self.queue.first().cloned() else {
+                self.cancel_live(0..);
+                // Whatever is queued next starts a new count.
+                self.played = 0;
+                return None;
+            };
+            match self.live_ids().iter().position(|id| *id == current.id) {
+                // If the preloaded track has moved up, this lets it start playing.
+                Some(position) => self.cancel_live(..position),
+                None if !open && !is_decoded(&decoded, &current) => return Some(current),
+                None => {
+                    self.cancel_live(0..);
+                    match take_or_open(&current, &mut decoded) {
+                        Ok(source) => self.append_source(current.id, source),
+                        Err(e) => {
+                            self.drop_unplayable(&current, &e);
+                            continue;
+                        }
+                    }
+                }
+            }
 
-    match args.command {
-        Commands::Daemon => {
-            let _ = run_daemon().await;
-        }
-        Commands::Play { track } => {
-            let res = send_command("play", Some(track)).await;
-            colored_print(res);
+            let wanted = self.next_track().map(|track| track.id);
+            let live = self.live_ids();
+            if live.len() <= 2 && live.get(1).copied() == wanted {
+                return None;
+            }
+            self.cancel_live(1..);
+            let next = self.next_track().cloned()?;
+            if !open && !is_decoded(&decoded, &next) {
+                return Some(next);
+            }
+            match take_or_open(&next, &mut decoded) {
+                Ok(source) => {
+                    self.append_source(next.id, source);
+                    return None;
+                }
+                Err(e) => self.drop_unplayable(&next, &e),
+            }
         }
-        Commands::Pause => {
-            let res = send_command("pause", None).await;
-            colored_print(res);
+    }
+
+    fn drop_unplayable(&mut self, track: &TrackInfo, error: &str) {
+        warn!("Dropping {} from the queue: {}", track.path, error);
+        self.queue.retain(|queued| queued.id != track.id);
+    }
+
+    fn live_ids(&self) -> Vec<u64> {
+        self.sources
+            .iter()
+            .filter(|slot| slot.is_live())
+            .map(|slot| slot.id)
+            .collect()
+    }
+
+    /// Cancels the live sources whose position (among live sources) falls in `range`.
+    fn cancel_live(&self, range: impl RangeBounds<usize>) {
+        for (position, slot) in self
+            .sources
+            .iter()
+            .filter(|slot| slot.is_live())
+            .enumerate()
+        {
+            if range.contains(&position) {
+                slot.cancelled.store(true, Ordering::SeqCst);
+                slot.ahead.release();
+            }
         }
-        Commands::Skip => {
-            let res = send_command("skip", None).await;
-            colored_print(res);
+        self.update_crossfade();
+    }
+
+    fn append_source(&mut self, id: u64, source: TrackSource) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let format = (source.channels(), source.sample_rate());
+        let (source, ahead) = Prefetch::new(source);
+        let source: TrackSource = Box::new(source);
+        // Trimmed before the crossfade, so tracks overlap where they're actually audible.
+        let (source, trimmed): (TrackSource, _) = match self.trim {
+            Some((threshold, max)) => {
+                let trimmed = Arc::new(Trimmed::default());
+                let source = TrimSilence::new(source, threshold, max, Arc::clone(&trimmed));
+                (Box::new(source), Some(trimmed))
+            }
+            None => (source, None),
+        };
+        // After trimming, so the pause is exactly as long as asked for.
+        let source: TrackSource = match self.gap.is_zero() {
+            true => source,
+            false => Box::new(Gap::new(source, self.gap)),
+        };
+        let gain = self
+            .queue
+            .iter()
+            .find(|track| track.id == id)
+            .and_then(|track| self.replay_gain(track));
+        let source: TrackSource = match gain {
+            Some(db) => Box::new(source.amplify(10f32.powf(db / 20.0))),
+            None => source,
+        };
+        let source: TrackSource = if self.crossfade.is_zero() {
+            source
+        } else {
+            Box::new(Crossfade::new(
+                source,
+                self.crossfade,
+                Arc::clone(&self.crossfade_bus),
+            ))
+        };
+        // There's nothing to pan once both sides are the same signal.
+        let source: TrackSource = match self.mono {
+            true => Box::new(Mono::new(source)),
+            false => Box::new(Balance::new(source, Arc::clone(&self.balance))),
+        };
+        // After the balance, so each side hears what is actually played on the other.
+        let source: TrackSource = match self.crossfeed && !self.mono {
+            true => Box::new(Crossfeed::new(source)),
+            false => source,
+        };
+        let source = Equalizer::new(source, Arc::clone(&self.eq));
+        let source = TimeStretch::new(source, Arc::clone(&self.stretch));
+        // Ahead of the meter, so the levels show what the limiter lets through.
+        let source = ClipGuard::new(source, Arc::clone(&self.clip), self.limiter);
+        let source = Meter::new(source, Arc::clone(&self.meter));
+        self.sink
+            .append(Cancellable::new(source, Arc::clone(&cancelled)));
+        self.sources.push_back(Slot {
+            id,
+            cancelled,
+            format,
+            ahead,
+            prefetching: false,
+            trimmed,
+        });
+        self.update_crossfade();
+    }
+
+    fn clear_sink(&mut self) {
+        self.sink.clear();
+        self.sources.clear();
+        self.update_crossfade();
+    }
+
+    /// The gain in dB to play `track` at under the normalization mode, lowered if needed so
+    /// the tagged peak doesn't clip. `None` when normalization is off or the tags are missing.
+    fn replay_gain(&self, track: &TrackInfo) -> Option<f32> {
+        let tags = track.replay_gain;
+        let track_values = tags.track_gain.map(|gain| (gain, tags.track_peak));
+        let album_values = tags.album_gain.map(|gain| (gain, tags.album_peak));
+        let (gain, peak) = match self.normalize {
+            Normalize::Off => return None,
+            Normalize::Track => track_values.or(album_values)?,
+            Normalize::Album => album_values.or(track_values)?,
+        };
+        match peak.filter(|peak| *peak > 0.0) {
+            Some(peak) => Some(gain.min(-20.0 * peak.log10())),
+            None => Some(gain),
         }
-        Commands::Queue { track } => {
-            let res = send_command("queue", Some(track)).await;
-            colored_print(res);
+    }
+
+    /// Starts decoding the next track in the background once the current one is within the
+    /// prefetch window of its end. Tracks of unknown length never get that close.
+    fn prefetch_next(&mut self) {
+        if self.prefetch.is_zero() {
+            return;
         }
-        Commands::Clear => {
-            let res = send_command("clear", None).await;
-            colored_print(res);
+        let Some(duration) = self.queue.first().and_then(|track| track.duration) else {
+            return;
+        };
+        if duration.saturating_sub(self.position()) > self.prefetch {
+            return;
         }
-        Commands::Resume => {
-            let res = send_command("resume", None).await;
-            colored_print(res);
+        let window = self.prefetch;
+        let Some(next) = self.sources.iter_mut().filter(|slot| slot.is_live()).nth(1) else {
+            return;
+        };
+        if next.prefetching {
+            return;
         }
-        Commands::Current => {
-            let res = send_command("current", None).await;
-            colored_print(res);
+        next.prefetching = true;
+        let ahead = Arc::clone(&next.ahead);
+        let cancelled = Arc::clone(&next.cancelled);
+        tokio::task::spawn_blocking(move || ahead.fill(window, &cancelled));
+    }
+
+    /// Lets the current track hand its tail to the next one only while a compatible next
+    /// track is actually waiting in the sink.
+    fn update_crossfade(&self) {
+        let mut live = self.sources.iter().filter(|slot| slot.is_live());
+        let ready = match (live.next(), live.next()) {
+            (Some(current), Some(next)) => current.format == next.format,
+            _ => false,
+        };
+        self.crossfade_bus.set_ready(ready);
+    }
+
+    /// Gives a track a queue id, so the sink can tell apart repeated entries of the same file.
+    fn assign_id(&mut self, metadata: &mut TrackInfo) -> u64 {
+        self.next_id += 1;
+        metadata.id = self.next_id;
+        metadata.id
+    }
+
+    pub fn push_to_queue(&mut self, source: TrackSource, metadata: TrackInfo) -> PlayerResult {
+        let name = metadata.name.clone();
+        self.append(Some(source), metadata);
+        Ok(PlayerResponse::new(format!(
+            "{} was successfully added to the queue",
+            name
+        )))
+    }
+
+    /// Adds a track to the end of the queue. Without a `source`, it's opened once it's due.
+    fn append(&mut self, source: Option<TrackSource>, mut metadata: TrackInfo) {
+        let id = self.assign_id(&mut metadata);
+        self.queue.push(metadata);
+        self.reconcile(source.map(|source| (id, source)));
+    }
+
+    /// How many more tracks fit in the queue once `placement` has been applied; playing
+    /// replaces the queue, so it always gets the full limit.
+    fn room(&self, placement: Placement) -> usize {
+        match placement {
+            Placement::Play | Placement::Cue => self.max_queue,
+            _ => self.max_queue.saturating_sub(self.queue.len()),
         }
     }
-}
 
-async fn run_daemon() {
-    println!("Initializing socket connection");
+    /// Inserts a track at `index` (clamped to the end of the queue) and returns where it landed.
+    /// Without a `source`, it's opened once it's due.
+    fn insert(
+        &mut self,
+        index: usize,
+        source: Option<TrackSource>,
+        mut metadata: TrackInfo,
+    ) -> usize {
+        let index = index.min(self.queue.len());
+        let id = self.assign_id(&mut metadata);
+        self.queue.insert(index, metadata);
+        self.reconcile(source.map(|source| (id, source)));
+        index
+    }
 
-    if Path::new(SOCKET_PATH).exists() {
-        let _ = std::fs::remove_file(SOCKET_PATH);
+    pub fn insert_at(
+        &mut self,
+        index: usize,
+        source: TrackSource,
+        metadata: TrackInfo,
+    ) -> PlayerResult {
+        let name = metadata.name.clone();
+        let index = self.insert(index, Some(source), metadata);
+        Ok(
+            PlayerResponse::new(format!("Inserted {} at position {}", name, index))
+                .with("index", index),
+        )
     }
-    let listener = tokio::net::UnixListener::bind(SOCKET_PATH).unwrap();
-    let mut player = Player::new();
 
-    loop {
-        let (mut stream, _) = listener.accept().await.unwrap();
-        let mut buf = vec![0u8; 1024];
+    pub fn play_next(&mut self, source: TrackSource, metadata: TrackInfo) -> PlayerResult {
+        let name = metadata.name.clone();
+        self.insert(1, Some(source), metadata);
+        Ok(PlayerResponse::new(format!("{} will play next", name)))
+    }
 
-        if let Ok(n) = stream.read(&mut buf).await {
-            if n == 0 {
-                return;
-            }
+    pub fn clear_queue(&mut self) -> PlayerResult {
+        if let Some(track) = self.queue.first().cloned() {
+            self.finished(&track, "stopped");
+        }
+        self.clear_sink();
+        self.queue.clear();
+        self.played = 0;
+        Ok(PlayerResponse::new("Queue was successfully cleared"))
+    }
 
-            let msg = &buf[..n];
+    /// Empties the upcoming queue and leaves the current track playing.
+    pub fn clear_upcoming(&mut self) -> PlayerResult {
+        if self.queue.len() <= 1 {
+            return Ok(PlayerResponse::new("No upcoming tracks to clear"));
+        }
+        let removed = self.queue.len() - 1;
+        self.queue.truncate(1);
+        self.reconcile(None);
+        Ok(PlayerResponse::new(format!(
+            "Cleared {} upcoming track(s)",
+            removed
+        )))
+    }
 
-            match serde_json::from_slice::<Command>(msg) {
-                Ok(cmd) => {
-                    let response = audio_controls(cmd, &mut player).await;
-                    let response_str = response.to_string();
-                    if let Err(e) = stream.write_all(response_str.as_bytes()).await {
-                        eprintln!("Failed to send response: {}", e);
-                    }
-                }
-                Err(e) => {
-                    let error_response = json!({
-                        "status": false,
-                        "message": format!("Invalid JSON: {}", e)
-                    });
-                    let _ = stream
-                        .write_all(error_response.to_string().as_bytes())
-                        .await;
+    /// Pauses playback. With fading on, this only starts the fade; `finish_ramp` pauses the
+    /// sink once it's silent.
+    pub fn pause(&mut self) -> PlayerResult {
+        if self.sink.len() == 0 {
+            return Err(PlayerError::Idle("Nothing is being played to pause".into()));
+        }
+
+        if self.sink.is_paused() || self.pausing() {
+            Ok(PlayerResponse::new("Already paused"))
+        } else {
+            match self.fade.is_zero() {
+                true => {
+                    self.sink.pause();
+                    self.stopped = false;
                 }
+                false => self.begin_ramp(true),
             }
+            Ok(PlayerResponse::new("Paused successfully"))
         }
     }
-}
 
-async fn audio_controls(cmd: Command, player: &mut Player) -> Value {
-    // Parse action
-    let action = match Actions::from_str(&cmd.action) {
-        Some(a) => a,
-        None => {
-            return json!({
-                "status": false,
-                "message": format!("Invalid action: {}", cmd.action)
-            });
+    pub fn resume(&mut self) -> PlayerResult {
+        if self.sink.len() == 0 {
+            return Err(PlayerError::Idle("Nothing to resume".into()));
         }
-    };
 
-    match action {
-        Actions::Play => {
-            let track = match cmd.track {
-                Some(t) => t,
-                None => {
-                    return json!({
-                        "status": false,
-                        "message": "No track specified"
-                    });
+        // A pause still fading out just turns around from wherever it got to.
+        if self.pausing() {
+            self.begin_ramp(false);
+            Ok(PlayerResponse::new("Resumed successfully"))
+        } else if self.sink.is_paused() {
+            self.stopped = false;
+            match self.fade.is_zero() {
+                true => self.sink.play(),
+                false => {
+                    self.sink.set_volume(0.0);
+                    self.sink.play();
+                    self.begin_ramp(false);
                 }
-            };
+            }
+            Ok(PlayerResponse::new("Resumed successfully"))
+        } else {
+            Ok(PlayerResponse::new("Already playing"))
+        }
+    }
 
-            let file = match File::open(&track) {
-                Ok(f) => f,
-                Err(e) => {
-                    return json!({
-                        "status": false,
-                        "message": format!("Failed to open file: {}", e)
-                    });
-                }
-            };
+    /// Pauses if playing and resumes if paused, for a single play/pause key.
+    pub fn toggle(&mut self) -> PlayerResult {
+        if self.sink.len() == 0 {
+            return Err(
+                PlayerError::Idle("Nothing is being played to toggle".into())
+                    .with("state", self.state()),
+            );
+        }
 
-            let source = match Decoder::new(BufReader::new(file)) {
-                Ok(s) => s,
-                Err(e) => {
-                    return json!({
-                        "status": false,
-                        "message": format!("Failed to decode audio: {}", e)
-                    });
-                }
-            };
+        let response = match self.sink.is_paused() || self.pausing() {
+            true => self.resume(),
+            false => self.pause(),
+        }?;
+        Ok(response.with("state", self.state()))
+    }
 
-            let duration = source.total_duration().unwrap_or_default();
-            let metadata = TrackInfo {
-                name: track.clone(),
-                duration: duration.as_secs() as usize,
-            };
-            player.play(source, metadata)
+    pub fn stop(&mut self) -> PlayerResult {
+        if self.sink.len() == 0 {
+            return Err(PlayerError::Idle("Nothing is being played to stop".into()));
         }
-        Actions::Pause => player.pause(),
-        Actions::Clear => player.clear_queue(),
-        Actions::Queue => {
-            let track = match cmd.track {
-                Some(t) => t,
-                None => {
-                    return json!({
-                        "status": false,
-                        "message": "No track specified"
-                    });
-                }
-            };
 
-            let file = match File::open(&track) {
-                Ok(f) => f,
-                Err(e) => {
-                    return json!({
-                        "status": false,
-                        "message": format!("Failed to open file: {}", e)
-                    });
-                }
-            };
+        if let Some(track) = self.queue.first() {
+            self.positions
+                .set(&track.path, self.position(), track.duration);
+        }
+        self.sink.pause();
+        self.stopped = true;
+        if let Err(e) = self.sink.try_seek(Duration::ZERO) {
+            return Ok(PlayerResponse::new(format!(
+                "Stopped, but could not rewind the track: {}",
+                e
+            )));
+        }
+        Ok(PlayerResponse::new("Stopped successfully"))
+    }
 
-            let source = match Decoder::new(BufReader::new(file)) {
-                Ok(s) => s,
-                Err(e) => {
-                    return json!({
-                        "status": false,
-                        "message": format!("Failed to decode audio: {}", e)
-                    });
+    /// Plays the current track again from the start. Seeking is tried first; sources that
+    /// can't seek, such as streams, are opened again from the track's path.
+    pub fn restart(&mut self) -> PlayerResult {
+        let Some(current) = self.queue.first().cloned() else {
+            return Err(PlayerError::Idle(
+                "Nothing is being played to restart".into(),
+            ));
+        };
+
+        let method = match self.sink.try_seek(Duration::ZERO) {
+            Ok(()) => "seek",
+            Err(e) => {
+                debug!("Seeking {} failed ({}); opening it again", current.name, e);
+                let source = match open_source(Path::new(&current.path)) {
+                    Ok(source) => source,
+                    Err(message) => {
+                        return Err(PlayerError::Playback(format!(
+                            "Could not restart {}: {}",
+                            current.name, message
+                        )));
+                    }
+                };
+                // A fresh id makes `reconcile` swap the playing source for the new one.
+                let mut metadata = current.clone();
+                let id = self.assign_id(&mut metadata);
+                if let Some((loop_id, _)) = self.loops.as_mut().filter(|(id, _)| *id == current.id)
+                {
+                    *loop_id = id;
                 }
-            };
+                self.queue[0] = metadata;
+                self.reconcile(Some((id, source)));
+                "reopen"
+            }
+        };
+        self.sink.play();
+        self.stopped = false;
+        Ok(PlayerResponse::new(format!("Restarted {}", current.name))
+            .with("track", current.name)
+            .with("method", method))
+    }
 
-            let duration = source.total_duration().unwrap_or_default();
-            let metadata = TrackInfo {
-                name: track.clone(),
-                duration: duration.as_secs() as usize,
-            };
-            player.push_to_queue(source, metadata)
+    /// Seeks the current track to where it was last left off, if it's long enough to have a
+    /// saved position. Returns the position resumed from.
+    pub fn resume_position(&mut self) -> Option<Duration> {
+        let track = self.queue.first()?;
+        let position = self.positions.get(&track.path)?;
+        match self.sink.try_seek(position) {
+            Ok(()) => Some(position),
+            Err(e) => {
+                warn!("Could not resume {}: {}", track.name, e);
+                None
+            }
         }
-        Actions::Skip => player.skip(),
-        Actions::Resume => player.resume(),
-        Actions::Current => player.current(),
     }
-}
 
-async fn send_command(action: &str, track: Option<String>) -> Value {
-    let mut stream = match UnixStream::connect(SOCKET_PATH).await {
-        Ok(stream) => stream,
+    /// Jumps within the current track. Percentages need the track's length, so they're
+    /// refused for live streams.
+    pub fn seek(&mut self, target: SeekTarget) -> PlayerResult {
+        let Some(track) = self.queue.first().cloned() else {
+            return Err(PlayerError::Idle("Nothing is playing".into()));
+        };
+        let position = match target {
+            SeekTarget::Seconds(seconds) => match Duration::try_from_secs_f64(seconds) {
+                Ok(position) => position,
+                Err(_) => {
+                    return Err(PlayerError::InvalidArgument(
+                        "seconds must be a non-negative number".into(),
+                    ));
+                }
+            },
+            SeekTarget::Percent(percent) => {
+                let Some(length) = track.duration else {
+                    return Err(PlayerError::Refused(format!(
+                        "Can't seek by percentage: the length of {} is unknown",
+                        track.name
+                    )));
+                };
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(PlayerError::InvalidArgument(
+                        "percent must be between 0 and 100".into(),
+                    ));
+                }
+                length.mul_f64(percent / 100.0)
+            }
+        };
+        if let Some(length) = track.duration
+            && position > length
+        {
+            return Err(PlayerError::InvalidArgument(format!(
+                "{} is only {} long",
+                track.name,
+                format_duration(length)
+            )));
+        }
+        if let Err(e) = self.sink.try_seek(position) {
+            return Err(PlayerError::Playback(format!(
+                "Could not seek in {}: {}",
+                track.name, e
+            )));
+        }
+        let percent = track
+            .duration
+            .filter(|length| !length.is_zero())
+            .map(|length| position.as_secs_f64() / length.as_secs_f64() * 100.0);
+        Ok(PlayerResponse::new(format!(
+            "Seeked to {} in {}",
+            format_duration(position),
+            track.name
+        ))
+        .with("position_secs", position.as_secs_f64())
+        .with("position", format_duration(position))
+        .with("percent", percent))
+    }
+
+    /// Marks the current position in the current track as `name`.
+    pub fn add_bookmark(&mut self, name: &str) -> PlayerResult {
+        let Some(track) = self.queue.first() else {
+            return Err(PlayerError::Idle("Nothing is playing".into()));
+        };
+        if track.duration.is_none() {
+            return Err(PlayerError::Refused(format!(
+                "Can't bookmark {}: live streams can't seek",
+                track.name
+            )));
+        }
+        let position = self.position();
+        self.bookmarks.add(&track.path, name, position);
+        Ok(PlayerResponse::new(format!(
+            "Bookmarked {} at {} in {}",
+            name,
+            format_duration(position),
+            track.name
+        ))
+        .with("position_secs", position.as_secs_f64())
+        .with("position", format_duration(position)))
+    }
+
+    pub fn list_bookmarks(&self) -> PlayerResult {
+        let Some(track) = self.queue.first() else {
+            return Err(PlayerError::Idle("Nothing is playing".into()));
+        };
+        let bookmarks: Vec<Value> = self
+            .bookmarks
+            .list(&track.path)
+            .into_iter()
+            .map(|(name, position)| {
+                json!({
+                    "name": name,
+                    "position_secs": position.as_secs_f64(),
+                    "position": format_duration(position)
+                })
+            })
+            .collect();
+        Ok(
+            PlayerResponse::new(format!("{} bookmark(s) in {}", bookmarks.len(), track.name))
+                .with("bookmarks", bookmarks),
+        )
+    }
+
+    /// The most recently finished or skipped tracks first, at most `limit` of them, with the
+    /// Unix time each one left the queue.
+    pub fn list_history(&self, limit: Option<usize>) -> PlayerResult {
+        let history: Vec<Value> = self
+            .history
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(track, at)| {
+                let mut entry = track.to_json();
+                entry["timestamp"] = json!(unix_secs(*at));
+                entry
+            })
+            .collect();
+        Ok(PlayerResponse::new(format!(
+            "{} of {} track(s) in history",
+            history.len(),
+            self.history.len()
+        ))
+        .with("history", history)
+        .with("total", self.history.len()))
+    }
+
+    /// Forgets the tracks `prev` goes back through. With `truncate_log` the `--history-log`
+    /// file is emptied too, and if that fails the history is left as it was.
+    pub fn clear_history(&mut self, truncate_log: bool) -> PlayerResult {
+        if truncate_log {
+            let Some(log) = self.history_log.as_mut() else {
+                return Err(PlayerError::NotFound(
+                    "The daemon wasn't started with --history-log".into(),
+                ));
+            };
+            if let Err(e) = log.truncate() {
+                return Err(PlayerError::Storage(format!(
+                    "Failed to truncate the history log: {}",
+                    e
+                )));
+            }
+            // The current track is logged again the next time it's seen playing.
+            self.log_pending = true;
+        }
+        let cleared = self.history.len();
+        self.history.clear();
+        let mut message = format!("Cleared {} track(s) from history", cleared);
+        if truncate_log {
+            message.push_str(" and emptied the history log");
+        }
+        Ok(PlayerResponse::new(message).with("cleared", cleared))
+    }
+
+    /// Seeks the current track to the bookmark called `name`. Bookmarks in other tracks
+    /// aren't reachable until that track is playing.
+    pub fn jump_to_bookmark(&mut self, name: &str) -> PlayerResult {
+        let Some(track) = self.queue.first().cloned() else {
+            return Err(PlayerError::Idle("Nothing is playing".into()));
+        };
+        let Some(position) = self.bookmarks.get(&track.path, name) else {
+            let message = match self.bookmarks.holder(name) {
+                Some(path) => format!("Bookmark {} is in {}, which isn't playing", name, path),
+                None => format!("No bookmark named {} in {}", name, track.name),
+            };
+            return Err(PlayerError::NotFound(message));
+        };
+        if let Err(e) = self.sink.try_seek(position) {
+            return Err(PlayerError::Playback(format!(
+                "Could not seek in {}: {}",
+                track.name, e
+            )));
+        }
+        Ok(PlayerResponse::new(format!(
+            "Jumped to {} at {}",
+            name,
+            format_duration(position)
+        ))
+        .with("position_secs", position.as_secs_f64())
+        .with("position", format_duration(position)))
+    }
+
+    /// Seeks the track that just started to `position`. On failure the track keeps playing
+    /// from the beginning and the reason is returned.
+    pub fn start_at(&mut self, position: Duration) -> Result<(), String> {
+        let Some(track) = self.queue.first() else {
+            return Err("nothing is playing".to_string());
+        };
+        if let Some(length) = track.duration
+            && position >= length
+        {
+            return Err(format!(
+                "the track is only {} long",
+                format_duration(length)
+            ));
+        }
+        self.sink.try_seek(position).map_err(|e| e.to_string())
+    }
+
+    /// Clears the saved position for `path`, so it plays from the start next time.
+    pub fn forget(&mut self, path: &str) -> PlayerResult {
+        if self.positions.forget(path) {
+            return Ok(PlayerResponse::new(format!(
+                "Forgot the position in {}",
+                path
+            )));
+        }
+        Err(PlayerError::NotFound(format!(
+            "No saved position for {}",
+            path
+        )))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.sink.empty() && !self.sink.is_paused()
+    }
+
+    /// One of "playing", "paused", "stopped", or "idle", for clients that track the player's
+    /// state without parsing messages.
+    pub fn state(&self) -> &'static str {
+        if self.queue.is_empty() || self.sink.empty() {
+            "idle"
+        } else if !self.sink.is_paused() && !self.pausing() {
+            "playing"
+        } else if self.stopped {
+            "stopped"
+        } else {
+            "paused"
+        }
+    }
+
+    /// Replaces whatever is playing (and the rest of the queue) with this track.
+    pub fn play(&mut self, source: TrackSource, metadata: TrackInfo) -> PlayerResult {
+        self.replace(source, metadata, false)
+    }
+
+    /// Like `play`, but leaves the track paused at its start.
+    pub fn cue(&mut self, source: TrackSource, metadata: TrackInfo) -> PlayerResult {
+        self.replace(source, metadata, true)
+    }
+
+    fn replace(
+        &mut self,
+        source: TrackSource,
+        mut metadata: TrackInfo,
+        paused: bool,
+    ) -> PlayerResult {
+        let source: TrackSource = if self.fade.is_zero() {
+            source
+        } else {
+            Box::new(source.fade_in(self.fade))
+        };
+        if let Some(track) = self.queue.first().cloned() {
+            self.finished(&track, "stopped");
+        }
+        self.clear_sink();
+        self.queue.clear();
+        self.played = 0;
+        let id = self.assign_id(&mut metadata);
+        self.queue.push(metadata.clone());
+        self.reconcile(Some((id, source)));
+        // Clearing the sink already paused it.
+        if !paused {
+            self.sink.play();
+        }
+        self.stopped = false;
+
+        let message = match paused {
+            true => format!("Paused on {}", metadata.name),
+            false => format!("Now playing {}", metadata.name),
+        };
+        Ok(PlayerResponse::new(message))
+    }
+
+    /// Skips `count` tracks, or as many as the queue holds. The sink is only brought in line
+    /// once at the end, so nothing between the first and last track starts decoding.
+    pub fn skip(&mut self, count: usize) -> PlayerResult {
+        if count == 0 {
+            return Err(PlayerError::InvalidArgument(
+                "Skip count must be at least 1".into(),
+            ));
+        }
+        if self.queue.is_empty() && self.sink.len() == 0 {
+            Err(PlayerError::Idle("Nothing to skip".into()))
+        } else {
+            if !self.queue.is_empty() {
+                // Under `repeat all` the queue is a cycle, so only the remainder of a lap
+                // matters; otherwise there's nothing past the end to skip.
+                let count = match self.repeat {
+                    RepeatMode::All => (count - 1) % self.queue.len() + 1,
+                    _ => count.min(self.queue.len()),
+                };
+                let first = self.skip_one(true);
+                let mut skipped = 1;
+                while skipped < count {
+                    self.skip_one(false);
+                    skipped += 1;
+                }
+                self.reconcile(None);
+
+                let mut message = match skipped {
+                    1 => format!("Skipped {}", first.name),
+                    n => format!("Skipped {} tracks", n),
+                };
+                if self.queue.is_empty() {
+                    message.push_str("; the queue is now empty");
+                }
+                Ok(PlayerResponse::new(message)
+                    .with("skipped", skipped)
+                    .with("current", self.queue.first().map(TrackInfo::to_json)))
+            } else {
+                Err(PlayerError::Idle("Queue is empty".into()))
+            }
+        }
+    }
+
+    /// Takes the current track off the queue, keeping it in the cycle under `repeat all`.
+    /// Only a track that was `playing` has a position worth saving; the ones after it in a
+    /// multi-track skip never started.
+    fn skip_one(&mut self, playing: bool) -> TrackInfo {
+        let skipped = self.queue.remove(0);
+        if self.repeat == RepeatMode::All {
+            // Skipped tracks stay in the cycle.
+            self.queue.push(skipped.clone());
+        }
+        self.remember(skipped.clone());
+        if playing {
+            self.finished(&skipped, "skipped");
+        }
+        skipped
+    }
+
+    /// Starts playing the track at `index`, treating everything before it as skipped.
+    pub fn jump(&mut self, index: usize) -> PlayerResult {
+        if index >= self.queue.len() {
+            return Err(PlayerError::NotFound(format!(
+                "No track at index {}",
+                index
+            )));
+        }
+
+        if index > 0 {
+            let current = self.queue[0].clone();
+            self.finished(&current, "skipped");
+        }
+        let skipped: Vec<TrackInfo> = self.queue.drain(..index).collect();
+        for track in skipped {
+            if self.repeat == RepeatMode::All {
+                self.queue.push(track.clone());
+            }
+            self.remember(track);
+        }
+        self.reconcile(None);
+        self.sink.play();
+
+        let Some(current) = self.queue.first() else {
+            return Err(PlayerError::Idle("Nothing left to play".into()));
+        };
+        Ok(PlayerResponse::new(format!("Now playing {}", current.name))
+            .with("track", current.to_json()))
+    }
+
+    /// Goes back to the most recently finished or skipped track, pushing the current one back
+    /// to play after it.
+    pub fn prev(&mut self) -> PlayerResult {
+        let Some((previous, _)) = self.history.pop_back() else {
+            return Err(PlayerError::NotFound("No previous track".into()));
+        };
+
+        if self.repeat == RepeatMode::All && self.queue.len() > 1 {
+            // The previous track is still in the cycle, at the back of the queue.
+            self.queue.rotate_right(1);
+            self.played =
+                (self.played % self.queue.len() + self.queue.len() - 1) % self.queue.len();
+            self.reconcile(None);
+            return Ok(PlayerResponse::new(format!(
+                "Now playing {}",
+                self.queue[0].name
+            )));
+        }
+
+        let source = match open_source(Path::new(&previous.path)) {
+            Ok(source) => source,
+            Err(e) => {
+                return Err(PlayerError::Playback(format!(
+                    "{} is no longer available: {}",
+                    previous.path, e
+                )));
+            }
+        };
+        let name = previous.name.clone();
+        self.played = self.played.saturating_sub(1);
+        self.insert(0, Some(source), previous);
+        Ok(PlayerResponse::new(format!("Now playing {}", name)))
+    }
+
+    /// Where the current track sits, 1-based, and how many tracks that's out of: the ones
+    /// already played, the current one, and the ones still to come. Under `repeat all`
+    /// the queue already holds the whole cycle, so it's the place in that instead.
+    fn queue_position(&self) -> Option<(usize, usize)> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        match self.repeat {
+            RepeatMode::All => Some((self.played % self.queue.len() + 1, self.queue.len())),
+            _ => Some((self.played + 1, self.played + self.queue.len())),
+        }
+    }
+
+    pub fn current(&mut self) -> PlayerResult {
+        if let Some(current_track) = self.queue.first() {
+            let position = self.queue_position();
+            Ok(
+                PlayerResponse::new(format!("Currently playing {}", current_track.name))
+                    .with("state", self.state())
+                    .with("track", current_track.name.clone())
+                    .with("path", current_track.path.clone())
+                    .with("title", current_track.title.clone())
+                    .with("artist", current_track.artist.clone())
+                    .with("album", current_track.album.clone())
+                    .with("queue_length", self.queue.len())
+                    .with("current_index", position.map(|(index, _)| index))
+                    .with("queue_total", position.map(|(_, total)| total))
+                    .with("volume", self.volume)
+                    .with("muted", self.muted)
+                    .with("position_secs", self.position().as_secs_f64())
+                    .with(
+                        "duration_secs",
+                        current_track.duration.map(|d| d.as_secs_f64()),
+                    )
+                    .with("duration", current_track.duration.map(format_duration)),
+            )
+        } else {
+            Err(PlayerError::Idle("Nothing is being played".into()).with("state", self.state()))
+        }
+    }
+
+    /// Changes how the entry at `index` is shown in `current`, `list`, and the rest. The file
+    /// it plays from stays the same.
+    pub fn rename(&mut self, index: usize, name: &str) -> PlayerResult {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(PlayerError::InvalidArgument(
+                "The new name can't be empty".into(),
+            ));
+        }
+        let Some(track) = self.queue.get_mut(index) else {
+            return Err(PlayerError::NotFound(format!(
+                "No track at index {}",
+                index
+            )));
+        };
+        let old = std::mem::replace(&mut track.name, name.to_string());
+        Ok(PlayerResponse::new(format!("Renamed {} to {}", old, name))
+            .with("index", index)
+            .with("track", track.to_json()))
+    }
+
+    pub fn remove(&mut self, index: usize) -> PlayerResult {
+        if index >= self.queue.len() {
+            return Err(PlayerError::NotFound(format!(
+                "No track at index {}",
+                index
+            )));
+        }
+
+        let removed = self.queue.remove(index);
+        self.reconcile(None);
+        Ok(PlayerResponse::new(format!(
+            "Removed {} from the queue",
+            removed.name
+        )))
+    }
+
+    /// Where `path` sits in the queue, comparing resolved paths so `./a.mp3` finds `a.mp3`.
+    pub fn contains(&self, path: &str) -> PlayerResult {
+        let wanted = resume::key(path);
+        let positions: Vec<usize> = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| resume::key(&track.path) == wanted)
+            .map(|(index, _)| index)
+            .collect();
+        let message = match positions.is_empty() {
+            true => format!("{} isn't queued", path),
+            false => format!(
+                "{} is queued at {}",
+                path,
+                positions
+                    .iter()
+                    .map(|index| index.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        Ok(PlayerResponse::new(message)
+            .with("contains", !positions.is_empty())
+            .with("positions", positions))
+    }
+
+    /// Removes repeated queue entries, keeping the first of each, so the current track always
+    /// stays. Entries count as repeats when they resolve to the same file, or with `by` set to
+    /// "title" when they have the same title; untitled entries are then compared by file.
+    pub fn dedup(&mut self, by: Option<&str>) -> PlayerResult {
+        let by_title = match by.unwrap_or("path") {
+            "path" => false,
+            "title" => true,
+            other => {
+                return Err(PlayerError::InvalidArgument(format!(
+                    "Can't deduplicate by '{}'; use path or title",
+                    other
+                )));
+            }
+        };
+
+        let key = |track: &TrackInfo| match &track.title {
+            Some(title) if by_title => format!("title:{}", title.to_lowercase()),
+            _ => {
+                let path = std::fs::canonicalize(&track.path)
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|_| track.path.clone());
+                format!("path:{}", path)
+            }
+        };
+        let before = self.queue.len();
+        let mut seen = std::collections::HashSet::new();
+        self.queue.retain(|track| seen.insert(key(track)));
+        let removed = before - self.queue.len();
+        self.reconcile(None);
+        Ok(
+            PlayerResponse::new(format!("Removed {} duplicate(s)", removed))
+                .with("removed", removed),
+        )
+    }
+
+    /// Moves the entry at `from` so it ends up at `to`. Moving the current track, or moving
+    /// something in front of it, changes what is playing, so that needs `force`.
+    pub fn move_track(&mut self, from: usize, to: usize, force: bool) -> PlayerResult {
+        let length = self.queue.len();
+        if from >= length || to >= length {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Indices must be below {}",
+                length
+            )));
+        }
+        if (from == 0 || to == 0) && from != to && !force {
+            return Err(PlayerError::Refused(
+                "Index 0 is the current track; use --force to move it".into(),
+            ));
+        }
+
+        let track = self.queue.remove(from);
+        let name = track.name.clone();
+        self.queue.insert(to, track);
+        self.reconcile(None);
+        Ok(
+            PlayerResponse::new(format!("Moved {} to position {}", name, to))
+                .with("tracks", self.track_list()),
+        )
+    }
+
+    /// Exchanges two upcoming queue entries. The current track stays where it is; `move
+    /// --force` is the way to replace it.
+    pub fn swap(&mut self, a: usize, b: usize) -> PlayerResult {
+        let length = self.queue.len();
+        if a >= length || b >= length {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Indices must be below {}",
+                length
+            )));
+        }
+        if a == 0 || b == 0 {
+            return Err(PlayerError::Refused(
+                "Index 0 is the current track; use move --force to replace it".into(),
+            ));
+        }
+
+        self.queue.swap(a, b);
+        self.reconcile(None);
+        let message = format!("Swapped {} and {}", self.queue[b].name, self.queue[a].name);
+        Ok(PlayerResponse::new(message).with("tracks", self.track_list()))
+    }
+
+    pub fn set_repeat(&mut self, mode: &str) -> PlayerResult {
+        let Some(mode) = RepeatMode::from_str(mode) else {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Invalid repeat mode: {} (expected off, one, or all)",
+                mode
+            )));
+        };
+
+        if (self.repeat == RepeatMode::All) != (mode == RepeatMode::All) {
+            // The cycle, or the run through the queue without it, starts at the current track.
+            self.played = 0;
+        }
+        self.repeat = mode;
+        self.reconcile(None);
+        Ok(
+            PlayerResponse::new(format!("Repeat mode set to {}", mode.as_str()))
+                .with("repeat", mode.as_str()),
+        )
+    }
+
+    /// Elapsed time in the current track.
+    ///
+    /// rodio counts the samples the current source has produced, so this resets whenever
+    /// the sink moves to a new source and does not advance while paused. Time-stretched
+    /// samples are counted at playback rate, so the count is scaled back to the track's own
+    /// time; this is only exact if the speed hasn't changed during the track.
+    pub fn position(&self) -> Duration {
+        let position = if self.preserve_pitch {
+            self.sink.get_pos().mul_f32(self.speed)
+        } else {
+            self.sink.get_pos()
+        };
+        // The sink keeps counting through the `--gap-ms` silence after the track.
+        match self.queue.first().and_then(|track| track.duration) {
+            Some(duration) if !self.gap.is_zero() => position.min(duration),
+            _ => position,
+        }
+    }
+
+    /// Listening time left until the queue runs out at the current speed, or `None` when a
+    /// track of unknown length (such as a live stream) makes it impossible to tell.
+    pub fn remaining(&self) -> Option<Duration> {
+        let total = self
+            .queue
+            .iter()
+            .map(|track| track.duration)
+            .sum::<Option<Duration>>()?;
+        let repeats = match self.queue.first() {
+            Some(current) => current.duration?.mul_f64(self.loops_left() as f64),
+            None => Duration::ZERO,
+        };
+        let total = total + repeats;
+        let elapsed = match self.queue.is_empty() {
+            true => Duration::ZERO,
+            false => self.position(),
+        };
+        Some(total.saturating_sub(elapsed).div_f32(self.speed))
+    }
+
+    /// The settings the daemon is running with: the startup ones plus the player's own.
+    pub fn config(&self) -> PlayerResult {
+        let mut response = PlayerResponse::new("Daemon configuration");
+        for (key, value) in self.config.as_object().into_iter().flatten() {
+            response.insert(key, value);
+        }
+        Ok(response
+            .with("fade_ms", self.fade.as_millis() as u64)
+            .with("crossfade_ms", self.crossfade.as_millis() as u64)
+            .with("gap_ms", self.gap.as_millis() as u64)
+            .with("prefetch_ms", self.prefetch.as_millis() as u64)
+            .with("stream_chunk_kb", stream::buffers().0 / 1024)
+            .with("stream_buffer_kb", stream::buffers().1 / 1024)
+            .with("max_queue", self.max_queue)
+            .with("normalize", self.normalize.as_str())
+            .with("mono", self.mono)
+            .with("crossfeed", self.crossfeed)
+            .with("eq", self.eq_json())
+            .with("limiter", self.limiter)
+            .with("notify", self.notify))
+    }
+
+    /// Changes the buffer sizes used for tracks opened from now on: how much a stream reads
+    /// from the network at a time and how far ahead it may get, and how long before a track
+    /// is due it starts decoding. Leaving them all out just reports the current values.
+    pub fn tune(
+        &mut self,
+        chunk_kb: Option<usize>,
+        buffer_kb: Option<usize>,
+        prefetch_ms: Option<u64>,
+    ) -> PlayerResult {
+        let (chunk_size, read_ahead) = stream::buffers();
+        let chunk_size = chunk_kb.map_or(chunk_size, |kb| kb.saturating_mul(1024));
+        let read_ahead = buffer_kb.map_or(read_ahead, |kb| kb.saturating_mul(1024));
+        let prefetch = prefetch_ms.map_or(self.prefetch, Duration::from_millis);
+        let limits = stream::MIN_BUFFER..=stream::MAX_BUFFER;
+        if !limits.contains(&chunk_size) || !limits.contains(&read_ahead) {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Stream sizes must be between {} and {} KiB",
+                stream::MIN_BUFFER / 1024,
+                stream::MAX_BUFFER / 1024
+            )));
+        }
+        if read_ahead < chunk_size {
+            return Err(PlayerError::InvalidArgument(
+                "The stream buffer must hold at least one chunk".into(),
+            ));
+        }
+        if prefetch > MAX_PREFETCH {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Prefetch can be at most {} ms",
+                MAX_PREFETCH.as_millis()
+            )));
+        }
+
+        stream::set_buffers(chunk_size, read_ahead);
+        self.prefetch = prefetch;
+        let changed = chunk_kb.is_some() || buffer_kb.is_some() || prefetch_ms.is_some();
+        let message = format!(
+            "Streams read {} KiB at a time, {} KiB ahead; prefetch {} ms",
+            chunk_size / 1024,
+            read_ahead / 1024,
+            prefetch.as_millis()
+        );
+        if changed {
+            info!("Buffers tuned: {}", message);
+        }
+        Ok(PlayerResponse::new(message)
+            .with("stream_chunk_kb", chunk_size / 1024)
+            .with("stream_buffer_kb", read_ahead / 1024)
+            .with("prefetch_ms", prefetch.as_millis() as u64))
+    }
+
+    /// Recent peak and RMS levels per channel as heard, so after volume and mute. Reads as
+    /// silence while nothing is playing.
+    pub fn levels(&self) -> PlayerResult {
+        let levels = self.meter.levels();
+        let fresh = self.is_playing()
+            && levels
+                .updated
+                .is_some_and(|updated| updated.elapsed() < LEVELS_STALE);
+        let gain = if fresh { self.sink.volume() } else { 0.0 };
+        let scale = |value: &f32| (value * gain).min(1.0);
+        let channels: Vec<Value> = levels
+            .peak
+            .iter()
+            .zip(&levels.rms)
+            .map(|(peak, rms)| json!({"peak": scale(peak), "rms": scale(rms)}))
+            .collect();
+        let peak = levels.peak.iter().map(scale).fold(0.0, f32::max);
+        Ok(PlayerResponse::new(format!("Peak {:.2}", peak))
+            .with("peak", peak)
+            .with("channels", channels))
+    }
+
+    /// Machine-readable snapshot of the player, meant for polling clients.
+    pub fn status(&self) -> PlayerResult {
+        let current_track = self.queue.first();
+        let state = self.state();
+        let is_playing = state == "playing";
+        let is_paused = matches!(state, "paused" | "stopped");
+        let message = match (state, current_track) {
+            ("playing", Some(track)) => format!("Playing {}", track.name),
+            ("paused", Some(track)) => format!("Paused {}", track.name),
+            ("stopped", Some(track)) => format!("Stopped {}", track.name),
+            _ => "Idle".to_string(),
+        };
+        // Read from the decoder, which has already decoded the first packet by the time it's
+        // handed over, so streams report it too. Unknown while stopped.
+        let slot = current_track.and_then(|track| {
+            self.sources
+                .iter()
+                .find(|slot| slot.id == track.id && slot.is_live())
+        });
+        let format = slot.map(|slot| slot.format);
+        // The trailing figure stays at zero until the track has been read to its end.
+        let trimmed = slot.and_then(|slot| slot.trimmed.as_deref());
+        let clipped = self.clip.clipped();
+        let position = self.queue_position();
+
+        Ok(PlayerResponse::new(message)
+            .with("state", state)
+            .with("is_playing", is_playing)
+            .with("is_paused", is_paused)
+            .with("volume", self.volume)
+            .with("muted", self.muted)
+            .with(
+                "position_secs",
+                current_track.map(|_| self.position().as_secs_f64()),
+            )
+            .with(
+                "duration_secs",
+                current_track
+                    .and_then(|t| t.duration)
+                    .map(|d| d.as_secs_f64()),
+            )
+            .with(
+                "duration",
+                current_track.and_then(|t| t.duration).map(format_duration),
+            )
+            .with("current_track", current_track.map(|t| t.name.clone()))
+            .with("current_path", current_track.map(|t| t.path.clone()))
+            .with("codec", current_track.and_then(|t| t.codec))
+            .with("sample_rate", format.map(|(_, rate)| rate))
+            .with("channels", format.map(|(channels, _)| channels))
+            .with("output_sample_rate", self.output_format.1)
+            .with("output_channels", self.output_format.0)
+            .with("queue_length", self.queue.len())
+            .with("current_index", position.map(|(index, _)| index))
+            .with("queue_total", position.map(|(_, total)| total))
+            .with(
+                "total_remaining_secs",
+                self.remaining().map(|d| d.as_secs_f64()),
+            )
+            .with("repeat", self.repeat.as_str())
+            .with("repeats_left", current_track.map(|_| self.loops_left()))
+            .with("speed", self.speed)
+            .with("preserve_pitch", self.preserve_pitch)
+            .with(
+                "balance",
+                f32::from_bits(self.balance.load(Ordering::Relaxed)),
+            )
+            .with("crossfade_ms", self.crossfade.as_millis() as u64)
+            .with("gap_ms", self.gap.as_millis() as u64)
+            .with("normalize", self.normalize.as_str())
+            .with("mono", self.mono)
+            .with("crossfeed", self.crossfeed)
+            .with("eq", self.eq_json())
+            .with("clipping", clipped > 0)
+            .with("clipped_samples", clipped)
+            .with(
+                "trimmed_start_ms",
+                trimmed.map(|t| t.start().as_millis() as u64),
+            )
+            .with(
+                "trimmed_end_ms",
+                trimmed.map(|t| t.end().as_millis() as u64),
+            )
+            .with(
+                "replay_gain_db",
+                current_track.and_then(|t| self.replay_gain(t)),
+            ))
+    }
+
+    fn track_list(&self) -> Vec<Value> {
+        self.queue
+            .iter()
+            .enumerate()
+            .map(|(index, track)| {
+                json!({
+                    "index": index,
+                    "name": track.name.clone(),
+                    "path": track.path.clone(),
+                    "title": track.title.clone(),
+                    "artist": track.artist.clone(),
+                    "album": track.album.clone(),
+                    "duration_secs": track.duration.map(|d| d.as_secs_f64()),
+                    "duration": track.duration.map(format_duration)
+                })
+            })
+            .collect()
+    }
+
+    pub fn list(&self) -> PlayerResult {
+        let tracks = self.track_list();
+        // Streams of unknown length don't count towards the total.
+        let total_duration: Duration = self.queue.iter().filter_map(|track| track.duration).sum();
+        let message = if self.queue.is_empty() {
+            "Queue is empty".to_string()
+        } else {
+            format!("{} track(s) in the queue", self.queue.len())
+        };
+
+        Ok(PlayerResponse::new(message)
+            .with("tracks", tracks)
+            .with("total_duration_secs", total_duration.as_secs_f64())
+            .with("total_duration", format_duration(total_duration))
+            .with(
+                "total_remaining_secs",
+                self.remaining().map(|d| d.as_secs_f64()),
+            ))
+    }
+
+    /// Randomly reorders the upcoming tracks, leaving the current one playing. A seed makes the
+    /// order reproducible.
+    pub fn shuffle(&mut self, seed: Option<u64>) -> PlayerResult {
+        if self.queue.is_empty() {
+            return Err(PlayerError::Idle("Queue is empty".into()));
+        }
+
+        let pending = &mut self.queue[1..];
+        shuffle(pending, seed);
+        let shuffled = pending.len();
+        self.reconcile(None);
+        Ok(
+            PlayerResponse::new(format!("Shuffled {} upcoming track(s)", shuffled))
+                .with("tracks", self.track_list()),
+        )
+    }
+
+    /// Puts the upcoming tracks in order by "name" (the tag title, or else the file name) or
+    /// "duration", leaving the current one playing. Tracks of unknown length go last.
+    pub fn sort(&mut self, key: &str, desc: bool) -> PlayerResult {
+        if !matches!(key, "name" | "duration") {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Can't sort by '{}'; use name or duration",
+                key
+            )));
+        }
+        if self.queue.is_empty() {
+            return Err(PlayerError::Idle("Queue is empty".into()));
+        }
+
+        let name = |track: &TrackInfo| {
+            let name = track.title.clone().unwrap_or_else(|| {
+                Path::new(&track.path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| track.path.clone())
+            });
+            name.to_lowercase()
+        };
+        let pending = &mut self.queue[1..];
+        match key {
+            "name" => pending.sort_by_cached_key(|track| name(track)),
+            _ => pending.sort_by_key(|track| (track.duration.is_none(), track.duration)),
+        }
+        if desc {
+            // Keep unknown lengths at the end rather than flipping them to the front.
+            let known = pending
+                .iter()
+                .filter(|track| key == "name" || track.duration.is_some())
+                .count();
+            pending[..known].reverse();
+        }
+        let sorted = pending.len();
+        self.reconcile(None);
+        Ok(
+            PlayerResponse::new(format!("Sorted {} upcoming track(s) by {}", sorted, key))
+                .with("tracks", self.track_list()),
+        )
+    }
+
+    pub fn volume(&self) -> PlayerResult {
+        let message = if self.muted {
+            format!("Volume is {:.2} (muted)", self.volume)
+        } else {
+            format!("Volume is {:.2}", self.volume)
+        };
+        Ok(PlayerResponse::new(message)
+            .with("volume", self.volume)
+            .with("muted", self.muted))
+    }
+
+    pub fn set_volume(&mut self, level: f32) -> PlayerResult {
+        if level.is_nan() || level < 0.0 {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Invalid volume: {}",
+                level
+            )));
+        }
+
+        self.volume = level.min(MAX_VOLUME);
+        self.apply_volume();
+        let message = if self.muted {
+            format!("Volume set to {:.2} (muted)", self.volume)
+        } else {
+            format!("Volume set to {:.2}", self.volume)
+        };
+        Ok(PlayerResponse::new(message)
+            .with("volume", self.volume)
+            .with("muted", self.muted))
+    }
+
+    /// Changes the playback speed, clamped to 0.25–4.0. rodio's own speed control shifts the
+    /// pitch along with the tempo; with `preserve_pitch` the sources are time-stretched instead.
+    pub fn set_speed(&mut self, factor: f32, preserve_pitch: bool) -> PlayerResult {
+        if factor.is_nan() || factor <= 0.0 {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Invalid speed: {}",
+                factor
+            )));
+        }
+
+        self.speed = factor.clamp(MIN_SPEED, MAX_SPEED);
+        self.preserve_pitch = preserve_pitch;
+        let (sink_speed, stretch) = if preserve_pitch {
+            (1.0, self.speed)
+        } else {
+            (self.speed, 1.0)
+        };
+        self.sink.set_speed(sink_speed);
+        self.stretch.store(stretch.to_bits(), Ordering::Relaxed);
+        let message = if preserve_pitch {
+            format!("Speed set to {:.2}x (pitch preserved)", self.speed)
+        } else {
+            format!("Speed set to {:.2}x", self.speed)
+        };
+        Ok(PlayerResponse::new(message)
+            .with("speed", self.speed)
+            .with("preserve_pitch", self.preserve_pitch))
+    }
+
+    /// Changes the gain of the equalizer bands that are given, leaving the others alone, and
+    /// clamps each to ±12 dB. Takes effect on the playing track straight away. Giving none
+    /// just reports the current gains.
+    pub fn set_eq(
+        &mut self,
+        low: Option<f32>,
+        mid: Option<f32>,
+        high: Option<f32>,
+    ) -> PlayerResult {
+        let mut gains = self.eq.get();
+        for (gain, given) in gains.iter_mut().zip([low, mid, high]) {
+            match given {
+                Some(value) if value.is_nan() => {
+                    return Err(PlayerError::InvalidArgument(format!(
+                        "Invalid EQ gain: {}",
+                        value
+                    )));
+                }
+                Some(value) => *gain = value.clamp(-MAX_EQ_GAIN, MAX_EQ_GAIN),
+                None => {}
+            }
+        }
+        self.eq.set(gains);
+        Ok(self.eq_response())
+    }
+
+    /// Sets every equalizer band back to 0 dB, which bypasses the filters.
+    pub fn reset_eq(&mut self) -> PlayerResult {
+        self.eq.set([0.0; EQ_BANDS.len()]);
+        Ok(self.eq_response())
+    }
+
+    fn eq_response(&self) -> PlayerResponse {
+        let gains = self.eq.get();
+        let message = match gains.iter().all(|gain| *gain == 0.0) {
+            true => "EQ off".to_string(),
+            false => {
+                let bands: Vec<String> = EQ_BANDS
+                    .iter()
+                    .zip(gains)
+                    .map(|((name, _), gain)| format!("{} {:+.1} dB", name, gain))
+                    .collect();
+                format!("EQ set to {}", bands.join(", "))
+            }
+        };
+        PlayerResponse::new(message).with("eq", self.eq_json())
+    }
+
+    /// The equalizer's gains in dB, by band name.
+    fn eq_json(&self) -> Value {
+        EQ_BANDS
+            .iter()
+            .zip(self.eq.get())
+            .map(|((name, _), gain)| (name.to_string(), json!(gain)))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// Biases output towards the left (negative) or right (positive) speaker, clamped to
+    /// -1.0–1.0. Takes effect on the playing track straight away.
+    pub fn set_balance(&mut self, value: f32) -> PlayerResult {
+        if value.is_nan() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Invalid balance: {}",
+                value
+            )));
+        }
+
+        let value = value.clamp(-1.0, 1.0);
+        self.balance.store(value.to_bits(), Ordering::Relaxed);
+        let mut message = match value {
+            0.0 => "Balance centred".to_string(),
+            v if v < 0.0 => format!("Balance set to {:.2} (left)", v),
+            v => format!("Balance set to {:.2} (right)", v),
+        };
+        if self.mono {
+            message.push_str("; it has no effect while the daemon runs with --mono");
+        }
+        Ok(PlayerResponse::new(message).with("balance", value))
+    }
+
+    /// Moves the volume by `delta`, clamped to 0.0–2.0. Works from the stored level, so
+    /// adjusting while muted changes what unmuting restores.
+    pub fn adjust_volume(&mut self, delta: f32) -> PlayerResult {
+        if delta.is_nan() {
+            return Err(PlayerError::InvalidArgument(format!(
+                "Invalid volume change: {}",
+                delta
+            )));
+        }
+        self.set_volume((self.volume + delta).clamp(0.0, MAX_VOLUME))
+    }
+
+    /// Whether a pause has been asked for but is still fading out.
+    fn pausing(&self) -> bool {
+        self.ramping.is_some_and(|ramp| ramp.pausing)
+    }
+
+    /// Starts fading down to a pause, or back up to the volume, from wherever the sink is now.
+    /// It takes over from any fade already running, including a `fadestop`.
+    fn begin_ramp(&mut self, pausing: bool) {
+        self.fades += 1;
+        self.fading = None;
+        self.ramping = Some(Ramp {
+            id: self.fades,
+            from: self.sink.volume(),
+            pausing,
+        });
+    }
+
+    /// Sets the sink `progress` of the way through fade `id`, and returns whether the fade
+    /// should go on. Stopping or emptying the sink calls it off and puts the volume back; a
+    /// newer fade just takes over.
+    fn ramp_step(&mut self, id: u64, progress: f32) -> bool {
+        let Some(ramp) = self.ramping.filter(|ramp| ramp.id == id) else {
+            return false;
+        };
+        if self.sink.is_paused() || self.sink.empty() {
+            self.ramping = None;
+            self.apply_volume();
+            return false;
+        }
+        let to = match (ramp.pausing, self.muted) {
+            (false, false) => self.volume,
+            _ => 0.0,
+        };
+        self.sink
+            .set_volume(ramp.from + (to - ramp.from) * progress);
+        true
+    }
+
+    /// Ends fade `id`, pausing if that's what it was for, then puts the level back for the
+    /// next resume.
+    fn finish_ramp(&mut self, id: u64) {
+        if !self.ramp_step(id, 1.0) {
+            return;
+        }
+        if self.pausing() {
+            self.sink.pause();
+            self.stopped = false;
+        }
+        self.ramping = None;
+        self.apply_volume();
+        self.publish_changes();
+    }
+
+    /// Starts fading out the current track, taking over from any fade already running.
+    /// Returns the fade's number, which it checks at every step to know it's still wanted.
+    fn begin_fade_out(&mut self) -> Result<u64, PlayerError> {
+        let track = match self.queue.first() {
+            Some(track) if self.sink.len() > 0 && !self.sink.is_paused() => track.id,
+            _ => {
+                return Err(PlayerError::Idle("Nothing is playing to fade out".into()));
+            }
+        };
+        self.fades += 1;
+        self.fading = Some((self.fades, track));
+        self.ramping = None;
+        Ok(self.fades)
+    }
+
+    /// Sets the sink to `level` of the user's volume for fade `id`, and returns whether the
+    /// fade should go on. Pausing, stopping, or changing track calls it off, and the volume
+    /// goes back to where it was; a newer fade just takes over.
+    fn fade_out_step(&mut self, id: u64, level: f32) -> bool {
+        let Some((fading, track)) = self.fading else {
+            return false;
+        };
+        if fading != id {
+            return false;
+        }
+        if self.sink.is_paused() || self.queue.first().map(|track| track.id) != Some(track) {
+            self.fading = None;
+            self.apply_volume();
+            return false;
+        }
+        let volume = if self.muted { 0.0 } else { self.volume };
+        self.sink.set_volume(volume * level);
+        true
+    }
+
+    /// Ends fade `id` by stopping playback, then puts the volume back so the next `play`
+    /// isn't silent.
+    fn finish_fade_out(&mut self, id: u64) {
+        if !self.fade_out_step(id, 0.0) {
+            return;
+        }
+        self.fading = None;
+        let response = self.stop();
+        self.apply_volume();
+        self.publish_changes();
+        match response {
+            Ok(response) => info!("fadestop: {}", response.message),
+            Err(e) => warn!("fadestop failed: {}", e),
+        }
+    }
+
+    fn apply_volume(&self) {
+        let volume = if self.muted { 0.0 } else { self.volume };
+        self.sink.set_volume(volume);
+        self.clip.set_gain(volume);
+    }
+
+    pub fn set_muted(&mut self, muted: bool) -> PlayerResult {
+        self.muted = muted;
+        self.apply_volume();
+        let message = if muted { "Muted" } else { "Unmuted" };
+        Ok(PlayerResponse::new(message)
+            .with("volume", self.volume)
+            .with("muted", self.muted))
+    }
+
+    pub fn toggle_mute(&mut self) -> PlayerResult {
+        self.set_muted(!self.muted)
+    }
+}
+
+/// The `track` field of `play` and `queue`: one path or URL, or several to load in order.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Tracks {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Tracks {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Tracks::One(track) => vec![track],
+            Tracks::Many(tracks) => tracks,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Command {
+    action: Actions,
+    token: Option<String>,
+    track: Option<Tracks>,
+    level: Option<f32>,
+    delta: Option<f32>,
+    index: Option<usize>,
+    from: Option<usize>,
+    to: Option<usize>,
+    #[serde(default)]
+    force: bool,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    next: bool,
+    #[serde(default)]
+    if_idle: bool,
+    mode: Option<String>,
+    seed: Option<u64>,
+    factor: Option<f32>,
+    #[serde(default)]
+    preserve_pitch: bool,
+    repeat: Option<usize>,
+    #[serde(default)]
+    keep_current: bool,
+    #[serde(default)]
+    dry_run: bool,
+    value: Option<f32>,
+    by: Option<String>,
+    key: Option<String>,
+    #[serde(default)]
+    desc: bool,
+    #[serde(default)]
+    resume: bool,
+    start: Option<f64>,
+    #[serde(default)]
+    truncate_log: bool,
+    seconds: Option<f64>,
+    percent: Option<f64>,
+    name: Option<String>,
+    count: Option<usize>,
+    limit: Option<usize>,
+    stream_chunk_kb: Option<usize>,
+    stream_buffer_kb: Option<usize>,
+    prefetch_ms: Option<u64>,
+    low: Option<f32>,
+    mid: Option<f32>,
+    high: Option<f32>,
+    a: Option<usize>,
+    b: Option<usize>,
+    path: Option<String>,
+    #[serde(default)]
+    paused: bool,
+}
+
+/// The JSON type an action expects for one of its fields.
+#[derive(Debug, Clone, Copy)]
+enum FieldKind {
+    String,
+    // A string, or a non-empty array of them.
+    Strings,
+    Number,
+    Index,
+}
+
+impl FieldKind {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            FieldKind::String => value.is_string(),
+            FieldKind::Strings => match value.as_array() {
+                Some(values) => !values.is_empty() && values.iter().all(Value::is_string),
+                None => value.is_string(),
+            },
+            FieldKind::Number => value.is_number(),
+            FieldKind::Index => value.is_u64(),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            FieldKind::String => "a string",
+            FieldKind::Strings => "a string or string array",
+            FieldKind::Number => "a numeric",
+            FieldKind::Index => "a non-negative integer",
+        }
+    }
+}
+
+/// Compares every byte no matter where the first mismatch is, so response times don't hint
+/// at how much of a guessed token was right. Only the length can leak.
+fn token_matches(expected: &str, given: Option<&str>) -> bool {
+    let given = given.unwrap_or_default().as_bytes();
+    let expected = expected.as_bytes();
+    if given.len() != expected.len() {
+        return false;
+    }
+    given
+        .iter()
+        .zip(expected)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Parses a request line, checking the action and its required fields before the general
+/// shape so clients get told exactly what's wrong.
+fn parse_command(line: &str) -> Result<Command, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+    if !value.is_object() {
+        return Err("Commands must be JSON objects".to_string());
+    }
+    let Some(action) = value["action"].as_str().map(str::to_string) else {
+        return Err("Every command needs a string 'action' field".to_string());
+    };
+    let Some(parsed) = Actions::from_str(&action) else {
+        return Err(format!("Unknown action '{}'", action));
+    };
+    for (field, kind) in parsed.required_fields() {
+        if !kind.matches(&value[field]) {
+            return Err(format!(
+                "{} requires {} '{}' field",
+                action,
+                kind.describe(),
+                field
+            ));
+        }
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid {} command: {}", action, e))
+}
+
+#[derive(Parser)]
+#[command(name = "socket_app", version)]
+struct Args {
+    /// Socket the daemon listens on [default: $XDG_RUNTIME_DIR/uds-audio.sock or /tmp/sound.sock]
+    #[arg(long, global = true, env = "UDS_AUDIO_SOCKET")]
+    socket: Option<PathBuf>,
+
+    /// Use the Linux abstract socket NAME instead of a socket file (Linux only)
+    #[arg(long, global = true, value_name = "NAME", conflicts_with = "socket")]
+    abstract_socket: Option<String>,
+
+    /// Shared secret: the daemon rejects commands without it, and the CLI sends it along
+    #[arg(long, global = true, value_name = "SECRET", env = "UDS_AUDIO_TOKEN")]
+    token: Option<String>,
+
+    /// Talk to a daemon started with `--listen` at ADDR:PORT instead of the local socket
+    #[arg(
+        long,
+        global = true,
+        value_name = "ADDR:PORT",
+        env = "UDS_AUDIO_CONNECT"
+    )]
+    connect: Option<String>,
+
+    /// Print the daemon's raw JSON response instead of a colored message
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Only print errors: hides success messages, and limits the daemon's logs to errors
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
+    /// Read defaults from this TOML file [default: $XDG_CONFIG_HOME/uds-audio/config.toml]
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        env = "UDS_AUDIO_CONFIG"
+    )]
+    config: Option<PathBuf>,
+
+    /// Times to retry connecting while the daemon isn't up yet, doubling the wait each time
+    #[arg(long, global = true, value_name = "N", default_value_t = 3)]
+    retries: u32,
+
+    /// Milliseconds to wait before the first retry
+    #[arg(long, global = true, value_name = "MS", default_value_t = 50)]
+    retry_delay: u64,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// Picks the socket path from the flag/env var, then `$XDG_RUNTIME_DIR`, then the legacy default.
+fn resolve_socket_path(socket: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = socket {
+        return path;
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Path::new(&dir).join(SOCKET_FILE_NAME),
+        _ => PathBuf::from(DEFAULT_SOCKET_PATH),
+    }
+}
+
+#[derive(clap::Args)]
+struct DaemonArgs {
+    /// Volume to start at, as set by the `volume` command
+    #[arg(long, default_value_t = 1.0)]
+    volume: f32,
+    /// Fade length in milliseconds when starting a track and pausing (0 disables fading)
+    #[arg(long, default_value_t = 300)]
+    fade_ms: u64,
+    /// Overlap the end of each track with the start of the next by this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    crossfade_ms: u64,
+    /// Play this many milliseconds of silence after each track (not with --crossfade-ms)
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "MS",
+        conflicts_with = "crossfade_ms"
+    )]
+    gap_ms: u64,
+    /// Start decoding the next track this many milliseconds before the current one ends, and
+    /// buffer that much of it (0 disables)
+    #[arg(long, default_value_t = 3000)]
+    prefetch_ms: u64,
+    /// Start playing this file, directory, playlist, or URL as soon as the daemon is up
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    autoplay: Option<String>,
+    /// Repeat mode to start in, as set by the `repeat` command
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = RepeatMode::Off)]
+    repeat: RepeatMode,
+    /// Show a desktop notification whenever a new track starts
+    #[arg(long)]
+    notify: bool,
+    /// Append a JSON line to this file for every track that starts playing
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    history_log: Option<PathBuf>,
+    /// Even out loudness between tracks using their ReplayGain tags
+    #[arg(long, value_enum, default_value_t = Normalize::Off)]
+    normalize: Normalize,
+    /// Sum both channels into one, for a single speaker (the balance is then ignored)
+    #[arg(long)]
+    mono: bool,
+    /// Blend a little of each stereo channel into the other, for easier listening on
+    /// headphones (no effect with --mono)
+    #[arg(long)]
+    crossfeed: bool,
+    /// Soften samples that the volume, EQ, or normalization push past full scale instead of
+    /// letting them clip
+    #[arg(long)]
+    limiter: bool,
+    /// Equalizer gains to start with, in dB, as set by the `eq` command
+    #[arg(
+        long,
+        value_name = "LOW,MID,HIGH",
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    eq: Option<Vec<f32>>,
+    /// Skip silence at the start of each track and stop before the silence at its end
+    #[arg(long)]
+    trim_silence: bool,
+    /// Level in dBFS below which `--trim-silence` treats audio as silent
+    #[arg(long, value_name = "DB", default_value_t = -60.0, allow_hyphen_values = true)]
+    trim_threshold_db: f32,
+    /// The most `--trim-silence` cuts from either end of a track, so quiet intros and
+    /// fade-outs survive
+    #[arg(long, value_name = "MS", default_value_t = 3000)]
+    trim_max_ms: u64,
+    /// Output device to play through (see `devices`); falls back to the default
+    #[arg(long)]
+    device: Option<String>,
+    /// Seconds a client has to send a complete request before it's disconnected (0 waits forever)
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    request_timeout: u64,
+    /// Refuse to queue more than this many tracks
+    #[arg(long, default_value_t = 1000)]
+    max_queue: usize,
+    /// How much to log
+    #[arg(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+    /// Append logs to this file instead of printing them to stdout
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    log_file: Option<PathBuf>,
+    /// Also accept connections over TCP at ADDR:PORT (unencrypted, see the README)
+    #[arg(long, value_name = "ADDR:PORT")]
+    listen: Option<String>,
+    /// Let desktop media keys and applets control playback over MPRIS (needs the `mpris` feature)
+    #[arg(long)]
+    mpris: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn as_level(&self) -> tracing::Level {
+        match self {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    Play {
+        /// Files, directories, playlists, or URLs; the first one plays and the rest are queued
+        #[arg(value_hint = ValueHint::FilePath, required = true)]
+        track: Vec<String>,
+        /// Descend into subdirectories when TRACK is a directory
+        #[arg(long, short)]
+        recursive: bool,
+        /// Do nothing if a track is already playing
+        #[arg(long)]
+        if_idle: bool,
+        /// List what would be loaded and whether each track can be played, without playing
+        #[arg(long)]
+        dry_run: bool,
+        /// Play the track N times before moving on to the rest of the queue
+        #[arg(long, value_name = "N")]
+        repeat: Option<usize>,
+        /// Carry on from where the track was last left off
+        #[arg(long)]
+        resume: bool,
+        /// Begin playback this many seconds into the track
+        #[arg(long, value_name = "SECONDS", conflicts_with = "resume")]
+        start_at: Option<f64>,
+    },
+    Pause,
+    Resume,
+    /// Pause if playing, resume if paused
+    Toggle,
+    /// Play the current track again from the beginning
+    Restart,
+    /// Jump to a point in the current track: SECONDS from the start, or a percentage like 50%
+    Seek {
+        #[arg(allow_hyphen_values = true)]
+        position: String,
+    },
+    /// Forget where a track was left off, so `play --resume` starts it from the beginning
+    Forget {
+        #[arg(value_hint = ValueHint::FilePath)]
+        track: String,
+    },
+    Daemon(DaemonArgs),
+    Queue {
+        /// Files, directories, playlists, or URLs, queued in the order given
+        #[arg(value_hint = ValueHint::FilePath, required = true)]
+        track: Vec<String>,
+        /// Descend into subdirectories when TRACK is a directory
+        #[arg(long, short)]
+        recursive: bool,
+        /// Insert right after the current track instead of at the end
+        #[arg(long)]
+        next: bool,
+        /// List what would be loaded and whether each track can be played, without queueing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the tracks that finished or were skipped, most recent first, or clear them
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        /// Show at most this many tracks
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Keep named snapshots of the queue and switch between them
+    Playlist {
+        #[command(subcommand)]
+        command: PlaylistCommand,
+    },
+    /// Mark named positions in the current track and jump back to them
+    Bookmark {
+        #[command(subcommand)]
+        command: BookmarkCommand,
+    },
+    /// Put a track at a position in the queue (0 is the current track, so at least 1)
+    Insert {
+        /// Where it goes; past the end of the queue appends
+        index: usize,
+        /// A file, directory, playlist, or URL
+        #[arg(value_hint = ValueHint::FilePath)]
+        track: String,
+        /// Descend into subdirectories when TRACK is a directory
+        #[arg(long, short)]
+        recursive: bool,
+    },
+    Clear {
+        /// Only remove the upcoming tracks and keep the current one playing
+        #[arg(long)]
+        keep_current: bool,
+    },
+    /// Skip the current track, or COUNT tracks
+    #[command(visible_alias = "next")]
+    Skip {
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    Current,
+    /// Set the volume (0.0 to 2.0), change it with a leading + or -, or show it
+    Volume {
+        #[arg(allow_negative_numbers = true)]
+        level: Option<String>,
+    },
+    Stop,
+    /// Fade out over SECONDS, then stop; the volume is back to normal for the next play
+    Fadestop {
+        seconds: f64,
+    },
+    Status,
+    List,
+    Remove {
+        index: usize,
+    },
+    /// Show the queue entry at INDEX as NAME, without changing the file it plays
+    Rename {
+        index: usize,
+        name: String,
+    },
+    /// Write the queue, current track first, to an .m3u playlist
+    Save {
+        #[arg(value_hint = ValueHint::FilePath)]
+        path: PathBuf,
+    },
+    /// Set the repeat mode: off, one, or all
+    Repeat {
+        mode: String,
+    },
+    /// Check whether a file is in the queue, and at which positions
+    Contains {
+        #[arg(value_hint = ValueHint::FilePath)]
+        track: String,
+    },
+    /// Remove repeated tracks from the queue, keeping the first of each
+    Dedup {
+        /// Treat tracks as the same when they're the same file or have the same title
+        #[arg(long, default_value = "path", value_parser = ["path", "title"])]
+        by: String,
+    },
+    /// Put the upcoming tracks in order by name or duration
+    Sort {
+        #[arg(value_parser = ["name", "duration"])]
+        key: String,
+        /// Sort from Z to A, or longest first
+        #[arg(long)]
+        desc: bool,
+    },
+    /// Randomly reorder the upcoming tracks
+    Shuffle {
+        /// Seed for a reproducible order
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Toggle mute, keeping the volume level for when it's turned back on
+    Mute,
+    Unmute,
+    /// Go back to the previously played track
+    Prev,
+    /// Print player events as JSON lines until interrupted
+    Subscribe,
+    /// Set the playback speed (0.25 to 4.0, 1.0 is normal)
+    Speed {
+        factor: f32,
+        /// Keep the original pitch instead of letting it rise and fall with the speed
+        #[arg(long)]
+        preserve_pitch: bool,
+    },
+    /// Shift output between speakers, from -1.0 (full left) through 0.0 to 1.0 (full right)
+    Balance {
+        #[arg(allow_negative_numbers = true)]
+        value: f32,
+    },
+    /// Boost or cut bass, mids, and treble in dB (-12 to 12), or show the current settings
+    #[command(args_conflicts_with_subcommands = true)]
+    Eq {
+        #[command(subcommand)]
+        command: Option<EqCommand>,
+        /// Gain below about 250 Hz
+        #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+        low: Option<f32>,
+        /// Gain around 1 kHz
+        #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+        mid: Option<f32>,
+        /// Gain above about 4 kHz
+        #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+        high: Option<f32>,
+    },
+    /// List the audio output devices the daemon can use with --device
+    Devices,
+    /// Start playing the track at INDEX, skipping the ones before it
+    Jump {
+        index: usize,
+    },
+    /// Move the queue entry at FROM to position TO
+    Move {
+        from: usize,
+        to: usize,
+        /// Allow moving the current track (index 0) or replacing it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Exchange the upcoming tracks at indices A and B
+    Swap {
+        a: usize,
+        b: usize,
+    },
+    /// Check that the daemon is running and responsive
+    Ping,
+    /// Stop playback, save the queue, and exit the daemon
+    Shutdown,
+    /// Show the running daemon's version and warn if it differs from this client
+    Version,
+    /// List the file formats this build can play
+    Formats,
+    /// Show the current peak and RMS level of each channel
+    Levels,
+    /// Type commands at a prompt over a single connection (`quit` or Ctrl-D to leave)
+    Repl,
+    /// Show the settings the daemon is running with
+    Config,
+    /// Change buffer sizes for tracks opened from now on (shows them when given no options)
+    Tune {
+        /// Bytes read from the network at a time by HTTP streams, in KiB
+        #[arg(long, value_name = "KIB")]
+        stream_chunk_kb: Option<usize>,
+        /// How far an HTTP stream may download ahead of playback, in KiB
+        #[arg(long, value_name = "KIB")]
+        stream_buffer_kb: Option<usize>,
+        /// Start decoding the next track this many milliseconds before it's due (0 disables)
+        #[arg(long, value_name = "MS")]
+        prefetch_ms: Option<u64>,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Subcommand)]
+enum BookmarkCommand {
+    /// Save the current position under NAME (replacing a bookmark with that name)
+    Add { name: String },
+    /// List the current track's bookmarks
+    List,
+    /// Seek to the bookmark called NAME in the current track
+    Jump { name: String },
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommand {
+    /// Save the queue as the playlist NAME (replacing a playlist with that name)
+    Save { name: String },
+    /// Replace the queue with the playlist NAME and start playing it
+    Load {
+        name: String,
+        /// Load it paused instead
+        #[arg(long)]
+        no_autoplay: bool,
+    },
+    /// List the saved playlists
+    List,
+}
+
+#[derive(Subcommand)]
+enum EqCommand {
+    /// Set every band back to 0 dB, bypassing the equalizer
+    #[command(visible_alias = "off")]
+    Reset,
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Forget the tracks `prev` goes back through
+    Clear {
+        /// Also empty the daemon's --history-log file
+        #[arg(long)]
+        log: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let json = args.json;
+    let mut config = match config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(message) => {
+            return exit_code(colored_print(
+                json!({"status": false, "message": message}),
+                json,
+            ));
+        }
+    };
+    let socket = match args.abstract_socket {
+        Some(name) => Endpoint::Abstract(name),
+        None => Endpoint::Path(resolve_socket_path(args.socket.or(config.socket.take()))),
+    };
+    // The daemon always binds its local socket; `--connect` only redirects the client.
+    let local = socket.clone();
+    let token = args.token.or(config.token.take());
+    let socket = Client {
+        endpoint: match args.connect {
+            Some(addr) => Endpoint::Tcp(addr),
+            None => socket,
+        },
+        token: token.clone(),
+        retries: args.retries,
+        retry_delay: Duration::from_millis(args.retry_delay),
+        session: None,
+    };
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    if !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+
+    let succeeded = match args.command {
+        Commands::Daemon(mut options) => {
+            let config_file = config.path.clone();
+            if let Some(matches) = matches.subcommand_matches("daemon") {
+                config.apply(&mut options, matches);
+            }
+            if args.quiet {
+                options.log_level = LogLevel::Error;
+            }
+            run_daemon(&local, options, token, config_file).await
+        }
+        Commands::Repl => repl(&socket, json).await,
+        command => run_client(command, &socket, json).await,
+    };
+    exit_code(succeeded)
+}
+
+/// Sends one CLI command to the daemon and prints the answer. Returns whether it succeeded.
+async fn run_client(command: Commands, socket: &Client, json: bool) -> bool {
+    let request = match command.request() {
+        Some(Ok(request)) => request,
+        Some(Err(message)) => {
+            return colored_print(json!({"status": false, "message": message}), json);
+        }
+        None => return run_local(command, json),
+    };
+    match command {
+        Commands::Subscribe => subscribe(socket, request, json).await,
+        command => print_response(&command, send_command(socket, request).await, json),
+    }
+}
+
+/// Runs the commands that never reach the daemon.
+fn run_local(command: Commands, json: bool) -> bool {
+    match command {
+        Commands::Formats => print_formats(list_formats(), json),
+        Commands::Completions { shell } => {
+            print_completions(shell);
+            true
+        }
+        // Devices are enumerated locally, so this works without a running daemon.
+        Commands::Devices => print_devices(list_devices(), json),
+        _ => colored_print(
+            json!({"status": false, "message": "Not available inside the REPL"}),
+            json,
+        ),
+    }
+}
+
+impl Commands {
+    /// The request this command sends to the daemon, or `None` for the ones the client
+    /// handles by itself. Arguments the client checks first can fail here.
+    fn request(&self) -> Option<Result<Value, String>> {
+        let request = match self {
+            Commands::Daemon(_)
+            | Commands::Repl
+            | Commands::Formats
+            | Commands::Devices
+            | Commands::Completions { .. } => return None,
+            Commands::Play {
+                track,
+                recursive,
+                if_idle,
+                dry_run,
+                repeat,
+                resume,
+                start_at,
+            } => Actions::Play.request(json!({
+                "track": track_field(track),
+                "recursive": recursive,
+                "if_idle": if_idle,
+                "dry_run": dry_run,
+                "repeat": repeat,
+                "resume": resume,
+                "start": start_at
+            })),
+            Commands::Pause => Actions::Pause.request(json!({})),
+            Commands::Skip { count } => Actions::Skip.request(json!({"count": count})),
+            Commands::Queue {
+                track,
+                recursive,
+                next,
+                dry_run,
+            } => Actions::Queue.request(json!({
+                "track": track_field(track),
+                "recursive": recursive,
+                "next": next,
+                "dry_run": dry_run
+            })),
+            Commands::History {
+                command: None,
+                limit,
+            } => Actions::History.request(json!({"limit": limit})),
+            Commands::History {
+                command: Some(HistoryCommand::Clear { log }),
+                ..
+            } => Actions::HistoryClear.request(json!({"truncate_log": log})),
+            Commands::Bookmark { command } => match command {
+                BookmarkCommand::Add { name } => {
+                    Actions::BookmarkAdd.request(json!({"name": name}))
+                }
+                BookmarkCommand::List => Actions::BookmarkList.request(json!({})),
+                BookmarkCommand::Jump { name } => {
+                    Actions::BookmarkJump.request(json!({"name": name}))
+                }
+            },
+            Commands::Playlist { command } => match command {
+                PlaylistCommand::Save { name } => {
+                    Actions::PlaylistSave.request(json!({"name": name}))
+                }
+                PlaylistCommand::Load { name, no_autoplay } => {
+                    Actions::PlaylistLoad.request(json!({"name": name, "paused": no_autoplay}))
+                }
+                PlaylistCommand::List => Actions::PlaylistList.request(json!({})),
+            },
+            Commands::Insert {
+                index,
+                track,
+                recursive,
+            } => Actions::Insert.request(json!({
+                "index": index,
+                "track": track,
+                "recursive": recursive
+            })),
+            Commands::Clear { keep_current } => {
+                Actions::Clear.request(json!({"keep_current": keep_current}))
+            }
+            Commands::Forget { track } => Actions::Forget.request(json!({"track": track})),
+            Commands::Restart => Actions::Restart.request(json!({})),
+            Commands::Seek { position } => match parse_seek(position) {
+                Ok(SeekTarget::Seconds(seconds)) => {
+                    Actions::Seek.request(json!({"seconds": seconds}))
+                }
+                Ok(SeekTarget::Percent(percent)) => {
+                    Actions::Seek.request(json!({"percent": percent}))
+                }
+                Err(message) => return Some(Err(message)),
+            },
+            Commands::Toggle => Actions::Toggle.request(json!({})),
+            Commands::Resume => Actions::Resume.request(json!({})),
+            Commands::Current => Actions::Current.request(json!({})),
+            Commands::Volume { level } => match level.as_deref().map(parse_volume) {
+                None => Actions::Volume.request(json!({})),
+                Some(Ok(VolumeChange::Absolute(level))) => {
+                    Actions::Volume.request(json!({"level": level}))
+                }
+                Some(Ok(VolumeChange::Relative(delta))) => {
+                    Actions::Volume.request(json!({"delta": delta}))
+                }
+                Some(Err(message)) => return Some(Err(message)),
+            },
+            Commands::Stop => Actions::Stop.request(json!({})),
+            Commands::Fadestop { seconds } => {
+                Actions::FadeStop.request(json!({"seconds": seconds}))
+            }
+            Commands::Status => Actions::Status.request(json!({})),
+            Commands::List => Actions::List.request(json!({})),
+            Commands::Remove { index } => Actions::Remove.request(json!({"index": index})),
+            Commands::Rename { index, name } => {
+                Actions::Rename.request(json!({"index": index, "name": name}))
+            }
+            Commands::Save { path } => {
+                // The daemon writes the file, so a relative path has to mean the same to it.
+                let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+                Actions::Save.request(json!({"path": path}))
+            }
+            Commands::Repeat { mode } => Actions::Repeat.request(json!({"mode": mode})),
+            Commands::Shuffle { seed } => Actions::Shuffle.request(json!({"seed": seed})),
+            Commands::Mute => Actions::Mute.request(json!({})),
+            Commands::Unmute => Actions::Unmute.request(json!({})),
+            Commands::Prev => Actions::Prev.request(json!({})),
+            Commands::Jump { index } => Actions::Jump.request(json!({"index": index})),
+            Commands::Subscribe => Actions::Subscribe.request(json!({})),
+            Commands::Ping => Actions::Ping.request(json!({})),
+            Commands::Shutdown => Actions::Shutdown.request(json!({})),
+            Commands::Version => Actions::Version.request(json!({})),
+            Commands::Move { from, to, force } => {
+                Actions::Move.request(json!({"from": from, "to": to, "force": force}))
+            }
+            Commands::Swap { a, b } => Actions::Swap.request(json!({"a": a, "b": b})),
+            Commands::Levels => Actions::Levels.request(json!({})),
+            Commands::Config => Actions::Config.request(json!({})),
+            Commands::Tune {
+                stream_chunk_kb,
+                stream_buffer_kb,
+                prefetch_ms,
+            } => Actions::Tune.request(json!({
+                "stream_chunk_kb": stream_chunk_kb,
+                "stream_buffer_kb": stream_buffer_kb,
+                "prefetch_ms": prefetch_ms
+            })),
+            Commands::Sort { key, desc } => {
+                Actions::Sort.request(json!({"key": key, "desc": desc}))
+            }
+            Commands::Contains { track } => Actions::Contains.request(json!({"track": track})),
+            Commands::Dedup { by } => Actions::Dedup.request(json!({"by": by})),
+            Commands::Balance { value } => Actions::Balance.request(json!({"value": value})),
+            Commands::Eq {
+                command: None,
+                low,
+                mid,
+                high,
+            } => Actions::Eq.request(json!({"low": low, "mid": mid, "high": high})),
+            Commands::Eq {
+                command: Some(EqCommand::Reset),
+                ..
+            } => Actions::EqReset.request(json!({})),
+            Commands::Speed {
+                factor,
+                preserve_pitch,
+            } => Actions::Speed.request(json!({
+                "factor": factor,
+                "preserve_pitch": preserve_pitch
+            })),
+        };
+        Some(Ok(request))
+    }
+}
+
+/// Prints the daemon's answer to `command` the way that command shows it.
+fn print_response(command: &Commands, mut res: Value, json: bool) -> bool {
+    match command {
+        Commands::Play { dry_run: true, .. } | Commands::Queue { dry_run: true, .. } => {
+            print_dry_run(res, json)
+        }
+        Commands::Play { .. } | Commands::Queue { .. } | Commands::Insert { .. } => {
+            print_enqueued(res, json)
+        }
+        Commands::History { .. } => print_history(res, json),
+        Commands::Bookmark { .. } => print_bookmarks(res, json),
+        Commands::Playlist { .. } => print_playlists(res, json),
+        Commands::List
+        | Commands::Shuffle { .. }
+        | Commands::Sort { .. }
+        | Commands::Move { .. }
+        | Commands::Swap { .. } => print_queue(res, json),
+        Commands::Levels => print_levels(res, json),
+        Commands::Config => print_config(res, json),
+        Commands::Current => {
+            if let Some(position) = queue_position(&res)
+                && !json
+            {
+                let message = res["message"].as_str().unwrap_or_default();
+                res["message"] = json!(format!("{} ({})", message, position));
+            }
+            colored_print(res, json)
+        }
+        Commands::Status => {
+            if res["queue_length"].as_u64().is_some_and(|len| len > 0) && !json {
+                let left = match res["total_remaining_secs"].is_number() {
+                    true => format_hms(secs_field(&res["total_remaining_secs"])),
+                    false => "unknown".to_string(),
+                };
+                let message = res["message"].as_str().unwrap_or_default();
+                let mut message = match queue_position(&res) {
+                    Some(position) => format!("{} ({}, {} left in queue)", message, position, left),
+                    None => format!("{} ({} left in queue)", message, left),
+                };
+                if let Some(format) = describe_format(&res) {
+                    message.push_str(&format!(" [{}]", format));
+                }
+                if res["clipping"].as_bool() == Some(true) {
+                    message.push_str(" [clipping]");
+                }
+                res["message"] = json!(message);
+            }
+            colored_print(res, json)
+        }
+        Commands::Ping => {
+            if let Some(uptime) = res["uptime_secs"].as_f64()
+                && !json
+            {
+                res["message"] = json!(format!(
+                    "pong (up {})",
+                    format_duration(Duration::from_secs_f64(uptime))
+                ));
+            }
+            colored_print(res, json)
+        }
+        Commands::Version => {
+            if let Some(daemon) = res["version"].as_str()
+                && daemon != env!("CARGO_PKG_VERSION")
+            {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Warning: this client is version {} but the daemon is {}; the protocol may differ",
+                        env!("CARGO_PKG_VERSION"),
+                        daemon
+                    )
+                    .yellow()
+                );
+            }
+            colored_print(res, json)
+        }
+        _ => colored_print(res, json),
+    }
+}
+
+/// Writes completions for the name this binary was invoked as, so they match whatever it
+/// was installed as.
+fn print_completions(shell: clap_complete::Shell) {
+    let mut command = Args::command();
+    let name = std::env::args()
+        .next()
+        .and_then(|arg0| {
+            Path::new(&arg0)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| command.get_name().to_string());
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
+
+fn exit_code(succeeded: bool) -> ExitCode {
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+enum VolumeChange {
+    Absolute(f32),
+    Relative(f32),
+}
+
+enum SeekTarget {
+    Seconds(f64),
+    Percent(f64),
+}
+
+/// "90" seeks to 90 seconds in; "50%" to halfway through.
+fn parse_seek(position: &str) -> Result<SeekTarget, String> {
+    let invalid = || {
+        format!(
+            "Invalid position: {} (expected seconds or a percentage)",
+            position
+        )
+    };
+    match position.strip_suffix('%') {
+        Some(percent) => Ok(SeekTarget::Percent(
+            percent.trim().parse().map_err(|_| invalid())?,
+        )),
+        None => Ok(SeekTarget::Seconds(
+            position.parse().map_err(|_| invalid())?,
+        )),
+    }
+}
+
+/// "0.5" sets the volume; "+0.1" and "-0.1" adjust it.
+fn parse_volume(level: &str) -> Result<VolumeChange, String> {
+    let value = level
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid volume: {}", level))?;
+    if level.starts_with('+') || level.starts_with('-') {
+        Ok(VolumeChange::Relative(value))
+    } else {
+        Ok(VolumeChange::Absolute(value))
+    }
+}
+
+/// Sends the daemon's logs to `--log-file`, or to stdout when there isn't one. A file that
+/// can't be opened falls back to stdout rather than stopping the daemon.
+fn init_logging(options: &DaemonArgs) {
+    let builder = tracing_subscriber::fmt().with_max_level(options.log_level.as_level());
+    let Some(path) = &options.log_file else {
+        builder.init();
+        return;
+    };
+    match std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        Ok(file) => builder
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(file))
+            .init(),
+        Err(e) => {
+            builder.init();
+            error!("Failed to open log file {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Serves commands until the daemon is told to stop. Returns false when it couldn't start.
+async fn run_daemon(
+    socket: &Endpoint,
+    options: DaemonArgs,
+    token: Option<String>,
+    config_file: Option<PathBuf>,
+) -> bool {
+    init_logging(&options);
+    info!("Initializing socket connection at {}", socket);
+
+    let listener = match socket.bind().await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", socket, e);
+            return false;
+        }
+    };
+    let tcp = match &options.listen {
+        Some(addr) => match TcpListener::bind(addr).await {
+            Ok(tcp) => {
+                warn!("Also listening on TCP {} without encryption", addr);
+                Some(tcp)
+            }
+            Err(e) => {
+                error!("Failed to listen on {}: {}", addr, e);
+                socket.cleanup();
+                return false;
+            }
+        },
+        None => None,
+    };
+    // The output stream can't move between threads, so it stays here while the player
+    // (which only holds the sink) is shared with the connection tasks.
+    let (output_stream, device) = devices::open_output_stream(options.device.as_deref());
+    let config = json!({
+        "socket": socket.to_string(),
+        "config_file": config_file,
+        "listen": options.listen,
+        "device": device,
+        "request_timeout_secs": options.request_timeout,
+        "token_required": token.is_some(),
+        "log_level": options.log_level.as_level().as_str().to_lowercase(),
+        "log_file": options.log_file,
+        "history_log": options.history_log,
+        "autoplay": options.autoplay,
+        "trim_silence": options.trim_silence,
+        "trim_threshold_db": options.trim_threshold_db,
+        "trim_max_ms": options.trim_max_ms,
+        "state_dir": state_dir(),
+        "mpris": options.mpris && cfg!(feature = "mpris"),
+    });
+    let output = output_stream.config();
+    let mut player = Player::new(
+        output_stream.mixer(),
+        (output.channel_count(), output.sample_rate()),
+        &options,
+        config,
+    );
+    restore_queue(&mut player);
+    if let Some(path) = &options.autoplay {
+        autoplay(&mut player, path);
+    }
+    let player = Arc::new(Mutex::new(player));
+    if options.mpris {
+        start_mpris(&player);
+    }
+    let request_timeout = Duration::from_secs(options.request_timeout);
+    let token: Option<Arc<str>> = token.as_deref().map(Arc::from);
+    serve(listener, tcp, Arc::clone(&player), request_timeout, token).await;
+
+    info!("Shutting down");
+    shutdown(socket, &mut *player.lock().await);
+    true
+}
+
+/// Answers clients, each on its own task, until a signal or `shutdown` stops the daemon.
+/// The queue is kept moving on a task of its own, so accepting never waits on the player.
+async fn serve(
+    listener: tokio::net::UnixListener,
+    tcp: Option<TcpListener>,
+    player: Arc<Mutex<Player>>,
+    request_timeout: Duration,
+    token: Option<Arc<str>>,
+) {
+    let started = Instant::now();
+    let stop = Arc::new(Notify::new());
+    let syncing = tokio::spawn(keep_in_sync(Arc::clone(&player)));
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    let mut sigint = signal(SignalKind::interrupt()).unwrap();
+    loop {
+        let (stream, local): (Box<dyn socket::Stream>, bool) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => (Box::new(stream), true),
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            accepted = accept_tcp(tcp.as_ref()) => match accepted {
+                Ok((stream, peer)) => {
+                    debug!("Accepted TCP connection from {}", peer);
+                    (Box::new(stream), false)
+                }
+                Err(e) => {
+                    warn!("Failed to accept TCP connection: {}", e);
+                    continue;
+                }
+            },
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
+            _ = stop.notified() => break,
+        };
+        tokio::spawn(handle_connection(
+            stream,
+            Arc::clone(&player),
+            started,
+            Arc::clone(&stop),
+            request_timeout,
+            local,
+            token.clone(),
+        ));
+    }
+    syncing.abort();
+}
+
+/// Keeps the queue moving even while no client is talking to us. Tracks that come due are
+/// opened on a blocking thread rather than under the player, so a slow disk or stream
+/// doesn't hold up commands.
+async fn keep_in_sync(player: Arc<Mutex<Player>>) {
+    let mut timer = tokio::time::interval(SYNC_INTERVAL);
+    let mut decoded = None;
+    loop {
+        if decoded.is_none() {
+            timer.tick().await;
+        }
+        let due = {
+            let mut player = player.lock().await;
+            player.sync_queue_with(decoded.take(), false)
+        };
+        let Some(track) = due else {
+            continue;
+        };
+        let path = PathBuf::from(&track.path);
+        match tokio::task::spawn_blocking(move || open_source(&path)).await {
+            Ok(Ok(source)) => decoded = Some((track.id, source)),
+            Ok(Err(e)) => {
+                let mut player = player.lock().await;
+                player.drop_unplayable(&track, &e);
+                player.publish_changes();
+            }
+            Err(e) => warn!("Failed to open {}: {}", track.path, e),
+        }
+    }
+}
+
+#[cfg(feature = "mpris")]
+fn start_mpris(player: &Arc<Mutex<Player>>) {
+    tokio::spawn(mpris::serve(Arc::clone(player)));
+}
+
+#[cfg(not(feature = "mpris"))]
+fn start_mpris(_player: &Arc<Mutex<Player>>) {
+    warn!("This build has no MPRIS support; rebuild with `--features mpris` to use --mpris");
+}
+
+/// Waits for the next TCP client, or forever when the daemon wasn't started with `--listen`.
+async fn accept_tcp(
+    tcp: Option<&TcpListener>,
+) -> std::io::Result<(tokio::net::TcpStream, std::net::SocketAddr)> {
+    match tcp {
+        Some(tcp) => tcp.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_connection(
+    stream: Box<dyn socket::Stream>,
+    player: Arc<Mutex<Player>>,
+    started: Instant,
+    stop: Arc<Notify>,
+    request_timeout: Duration,
+    local: bool,
+    token: Option<Arc<str>>,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut msg = String::new();
+
+    // Most connections carry one request. After `session` they keep taking requests until
+    // the client hangs up.
+    let mut session = false;
+    loop {
+        // A session may sit idle between requests, so its clock starts with the first byte.
+        if session && reader.fill_buf().await.is_ok_and(|buf| buf.is_empty()) {
+            return;
+        }
+        // Messages are newline-delimited, so keep reading until a full line arrives. A client
+        // that never finishes its line is dropped rather than holding the task forever.
+        let read = read_request(&mut reader, &mut msg);
+        let read = match request_timeout.is_zero() {
+            true => read.await,
+            false => match tokio::time::timeout(request_timeout, read).await {
+                Ok(read) => read,
+                Err(_) => {
+                    debug!("Dropped a connection that sent no complete request in time");
+                    return;
+                }
+            },
+        };
+        let Ok(n) = read else {
+            return;
+        };
+        if n == 0 {
+            return;
+        }
+        // The rest of an overlong line is still unread, so the connection can't go on.
+        if n as u64 == MAX_REQUEST_LEN && !msg.ends_with('\n') {
+            warn!("Dropped a request longer than {} bytes", MAX_REQUEST_LEN);
+            let message = format!("request is longer than {} bytes", MAX_REQUEST_LEN);
+            respond(&mut writer, &Err(PlayerError::InvalidArgument(message))).await;
+            return;
+        }
+
+        debug!("Received {}", msg.trim_end());
+        let response = match parse_command(&msg) {
+            Ok(cmd)
+                if token
+                    .as_deref()
+                    .is_some_and(|token| !token_matches(token, cmd.token.as_deref())) =>
+            {
+                warn!("Rejected {} without a valid token", cmd.action.name());
+                Err(PlayerError::Refused("unauthorized".into()))
+            }
+            // Answered without waiting for the player, so a busy daemon still shows as alive.
+            Ok(cmd) if cmd.action == Actions::Ping => ping(started),
+            // Anyone who can reach the Unix socket is already local; TCP clients may not be.
+            Ok(cmd) if cmd.action == Actions::Shutdown && !local => {
+                warn!("Refused shutdown over TCP");
+                Err(PlayerError::Refused(
+                    "shutdown only works over the local socket".into(),
+                ))
+            }
+            Ok(cmd) if cmd.action == Actions::Shutdown => {
+                respond(&mut writer, &Ok(PlayerResponse::new("Shutting down"))).await;
+                stop.notify_one();
+                return;
+            }
+            Ok(cmd) if cmd.action == Actions::Subscribe => {
+                let events = player.lock().await.subscribe();
+                stream_events(&mut writer, events).await;
+                return;
+            }
+            // The fade carries on after the reply, so it can't hold the player meanwhile.
+            Ok(cmd) if cmd.action == Actions::FadeStop => {
+                let response = fade_stop(&player, cmd.seconds).await;
+                log_outcome(cmd.action.name(), &response);
+                response
+            }
+            Ok(cmd)
+                if matches!(
+                    cmd.action,
+                    Actions::Pause | Actions::Resume | Actions::Toggle
+                ) =>
+            {
+                let response = pause_or_resume(&player, cmd.action).await;
+                log_outcome(cmd.action.name(), &response);
+                response
+            }
+            Ok(cmd) if cmd.action == Actions::Session => {
+                session = true;
+                Ok(PlayerResponse::new("Session started"))
+            }
+            Ok(cmd) => {
+                let action = cmd.action;
+                // Directories and playlists are read first, so the player is only held for
+                // as long as the command takes.
+                let probes = probe_request(&cmd, &player).await;
+                let response = {
+                    let mut player = player.lock().await;
+                    let response = audio_controls(cmd, &mut player, probes).await;
+                    player.publish_changes();
+                    response
+                };
+                log_outcome(action.name(), &response);
+                response
+            }
+            Err(message) => {
+                warn!("Rejected request: {}", message);
+                Err(PlayerError::InvalidArgument(message))
+            }
+        };
+        respond(&mut writer, &response).await;
+        if !session {
+            return;
+        }
+        msg.clear();
+    }
+}
+
+/// Sends one response line. A client that has gone away only gets a warning in the log.
+async fn respond(writer: &mut (impl AsyncWriteExt + Unpin), response: &PlayerResult) {
+    let line = format!("{}\n", response::to_json(response));
+    if let Err(e) = writer.write_all(line.as_bytes()).await {
+        warn!("Failed to send response: {}", e);
+    }
+}
+
+fn log_outcome(action: &str, response: &PlayerResult) {
+    match response {
+        Ok(response) => info!("{}: {}", action, response.message),
+        Err(e) => warn!("{} failed: {}", action, e),
+    }
+}
+
+/// Starts fading out the current track over `seconds`, after which it stops.
+async fn fade_stop(player: &Arc<Mutex<Player>>, seconds: Option<f64>) -> PlayerResult {
+    let Some(length) = seconds.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) else {
+        return Err(PlayerError::InvalidArgument(
+            "seconds must be a non-negative number".into(),
+        ));
+    };
+    let id = player.lock().await.begin_fade_out()?;
+    tokio::spawn(fade_out(Arc::clone(player), id, length));
+    Ok(
+        PlayerResponse::new(format!("Fading out over {}s", length.as_secs_f64()))
+            .with("seconds", length.as_secs_f64()),
+    )
+}
+
+/// Steps fade `id` down to silence over `length`, then stops playback. The player is only
+/// held for each step, so other commands go on working during the fade.
+async fn fade_out(player: Arc<Mutex<Player>>, id: u64, length: Duration) {
+    let steps = (length.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+    for step in 1..steps {
+        tokio::time::sleep(FADE_STEP).await;
+        let level = 1.0 - step as f32 / steps as f32;
+        if !player.lock().await.fade_out_step(id, level) {
+            return;
+        }
+    }
+    tokio::time::sleep(FADE_STEP).await;
+    player.lock().await.finish_fade_out(id);
+}
+
+/// Pauses, resumes, or toggles playback. Any fade runs on its own task, stepped like
+/// `fade_out`, so the reply doesn't wait for it.
+async fn pause_or_resume(player: &Arc<Mutex<Player>>, action: Actions) -> PlayerResult {
+    let mut locked = player.lock().await;
+    locked.sync_queue();
+    let fades = locked.fades;
+    let response = match action {
+        Actions::Pause => locked.pause(),
+        Actions::Resume => locked.resume(),
+        _ => locked.toggle(),
+    };
+    locked.publish_changes();
+    // Starting a fade numbers it, so a new number means there's one to step.
+    if locked.fades != fades {
+        tokio::spawn(ramp(Arc::clone(player), locked.fades, locked.fade));
+    }
+    response
+}
+
+/// Steps pause or resume fade `id` over `length`, holding the player only for each step.
+async fn ramp(player: Arc<Mutex<Player>>, id: u64, length: Duration) {
+    let steps = (length.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+    for step in 1..steps {
+        tokio::time::sleep(FADE_STEP).await;
+        if !player
+            .lock()
+            .await
+            .ramp_step(id, step as f32 / steps as f32)
+        {
+            return;
+        }
+    }
+    tokio::time::sleep(FADE_STEP).await;
+    player.lock().await.finish_ramp(id);
+}
+
+fn ping(started: Instant) -> PlayerResult {
+    Ok(PlayerResponse::new("pong").with("uptime_secs", started.elapsed().as_secs_f64()))
+}
+
+/// Acknowledges a subscription, then forwards events one per line until the client goes away.
+async fn stream_events(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    mut events: broadcast::Receiver<Value>,
+) {
+    let ack = response::to_json(&Ok(PlayerResponse::new("Subscribed")));
+    if writer
+        .write_all(format!("{}\n", ack).as_bytes())
+        .await
+        .is_err()
+    {
+        return;
+    }
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // A slow reader misses events rather than holding up the player.
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                json!({"event": "lagged", "missed": missed})
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if writer
+            .write_all(format!("{}\n", event).as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Saves the queue, stops playback, and removes the socket file so the next daemon starts
+/// clean.
+fn shutdown(socket: &Endpoint, player: &mut Player) {
+    save_queue(player);
+    let _ = player.clear_queue();
+    socket.cleanup();
+}
+
+/// Reads one request line into `msg`, giving up after `MAX_REQUEST_LEN` bytes.
+async fn read_request(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    msg: &mut String,
+) -> std::io::Result<usize> {
+    reader.take(MAX_REQUEST_LEN).read_line(msg).await
+}
+
+/// Where the queue is kept between daemon runs: `$XDG_STATE_HOME/uds-audio/queue.m3u`,
+/// falling back to `~/.local/state`.
+fn saved_queue_path() -> Option<PathBuf> {
+    Some(state_dir()?.join(SAVED_QUEUE_FILE_NAME))
+}
+
+/// Where the daemon keeps state between runs: `$XDG_STATE_HOME/uds-audio`, falling back to
+/// `~/.local/state/uds-audio`.
+fn state_dir() -> Option<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("uds-audio"))
+}
+
+/// Writes the queue, current track first, so the next daemon can pick it back up. An empty
+/// queue removes any earlier save.
+fn save_queue(player: &Player) {
+    let Some(path) = saved_queue_path() else {
+        return;
+    };
+    if player.queue.is_empty() {
+        let _ = std::fs::remove_file(&path);
+        return;
+    }
+
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| playlist::write_m3u(&path, &queue_entries(player)));
+    if let Err(e) = saved {
+        error!("Failed to save queue to {}: {}", path.display(), e);
+    }
+}
+
+/// Writes the queue, current track first, to an `.m3u` playlist that `play` or `queue` can
+/// load again later. Each entry carries its name and length when known.
+fn save_playlist(player: &Player, path: &Path) -> PlayerResult {
+    // Saving over `song.mp3` by mistake would destroy it, so only playlist names are taken.
+    let is_m3u = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u") || ext.eq_ignore_ascii_case("m3u8"));
+    if !is_m3u {
+        return Err(PlayerError::InvalidArgument(format!(
+            "{} must end in .m3u or .m3u8",
+            path.display()
+        )));
+    }
+    if player.queue.is_empty() {
+        return Err(PlayerError::Idle(
+            "The queue is empty; nothing to save".into(),
+        ));
+    }
+
+    let entries = queue_entries(player);
+    if let Err(e) = playlist::write_m3u(path, &entries) {
+        return Err(PlayerError::Storage(format!(
+            "Failed to write {}: {}",
+            path.display(),
+            e
+        )));
+    }
+    info!("Saved {} tracks to {}", entries.len(), path.display());
+    Ok(PlayerResponse::new(format!(
+        "Saved {} track(s) to {}",
+        entries.len(),
+        path.display()
+    ))
+    .with("written", entries.len())
+    .with("path", path))
+}
+
+/// Where the playlist slot `name` is kept: `playlists/NAME.m3u` in the state directory.
+fn slot_path(name: &str) -> Result<PathBuf, PlayerError> {
+    // The name becomes a file name, so it mustn't be able to point anywhere else.
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+        return Err(PlayerError::InvalidArgument(format!(
+            "Invalid playlist name '{}'",
+            name
+        )));
+    }
+    let Some(dir) = state_dir() else {
+        return Err(PlayerError::Storage(
+            "No state directory to keep playlists in; set XDG_STATE_HOME or HOME".into(),
+        ));
+    };
+    Ok(dir.join(PLAYLISTS_DIR_NAME).join(format!("{}.m3u", name)))
+}
+
+/// Snapshots the queue, current track first, as the playlist slot `name`, replacing any
+/// earlier snapshot of that name.
+fn save_slot(player: &Player, name: &str) -> PlayerResult {
+    let path = slot_path(name)?;
+    if player.queue.is_empty() {
+        return Err(PlayerError::Idle(
+            "The queue is empty; nothing to save".into(),
+        ));
+    }
+
+    let entries = queue_entries(player);
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| playlist::write_m3u(&path, &entries));
+    if let Err(e) = saved {
+        return Err(PlayerError::Storage(format!(
+            "Failed to save playlist {} to {}: {}",
+            name,
+            path.display(),
+            e
+        )));
+    }
+    info!("Saved {} tracks as playlist {}", entries.len(), name);
+    Ok(PlayerResponse::new(format!(
+        "Saved {} track(s) as playlist {}",
+        entries.len(),
+        name
+    ))
+    .with("name", name)
+    .with("written", entries.len()))
+}
+
+/// Replaces the queue with the playlist slot `name` and plays it, or leaves it paused on
+/// its first track.
+fn load_slot(player: &mut Player, name: &str, paused: bool, probes: &mut Probes) -> PlayerResult {
+    let path = slot_path(name)?;
+    if !path.exists() {
+        return Err(PlayerError::NotFound(format!(
+            "No playlist called {}",
+            name
+        )));
+    }
+    let placement = match paused {
+        true => Placement::Cue,
+        false => Placement::Play,
+    };
+    let probed = match probes.remove(&path) {
+        Some(probed) => probed,
+        None => probe_playlist(&path, player.room(placement)),
+    }?;
+    let mut response = enqueue_playlist(player, &path, probed, placement)?;
+    let current = player
+        .queue
+        .first()
+        .map(|track| track.name.clone())
+        .unwrap_or_default();
+    let mut message = match paused {
+        true => format!("Loaded playlist {}, paused on {}", name, current),
+        false => format!("Loaded playlist {}, now playing {}", name, current),
+    };
+    let failed = response
+        .get("failed")
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len);
+    if failed > 0 {
+        message.push_str(&format!("; {} track(s) failed to load", failed));
+    }
+    let dropped = response
+        .get("dropped")
+        .and_then(Value::as_u64)
+        .unwrap_or_default();
+    if dropped > 0 {
+        message.push_str(&format!("; dropped {} over the queue limit", dropped));
+    }
+    info!("{}", message);
+    response.message = message;
+    Ok(response.with("name", name).with("paused", paused))
+}
+
+/// The saved playlist slots by name, each with how many tracks it holds.
+fn list_slots() -> PlayerResult {
+    let Some(dir) = state_dir().map(|dir| dir.join(PLAYLISTS_DIR_NAME)) else {
+        return Err(PlayerError::Storage(
+            "No state directory to keep playlists in; set XDG_STATE_HOME or HOME".into(),
+        ));
+    };
+    // Nothing has been saved yet.
+    let files = std::fs::read_dir(&dir).into_iter().flatten().flatten();
+    let mut slots: Vec<(String, usize)> = files
+        .filter_map(|file| {
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != "m3u") {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            let tracks = playlist::parse_m3u(&path).map_or(0, |entries| entries.len());
+            Some((name, tracks))
+        })
+        .collect();
+    slots.sort();
+    let playlists: Vec<Value> = slots
+        .iter()
+        .map(|(name, tracks)| json!({"name": name, "tracks": tracks}))
+        .collect();
+    Ok(
+        PlayerResponse::new(format!("{} saved playlist(s)", playlists.len()))
+            .with("playlists", playlists),
+    )
+}
+
+fn queue_entries(player: &Player) -> Vec<playlist::PlaylistEntry> {
+    player
+        .queue
+        .iter()
+        .map(|track| playlist::PlaylistEntry {
+            // Relative paths were relative to the daemon, not to the saved playlist.
+            path: if stream::is_url(&track.path) {
+                PathBuf::from(&track.path)
+            } else {
+                std::path::absolute(&track.path).unwrap_or_else(|_| PathBuf::from(&track.path))
+            },
+            title: Some(track.name.clone()),
+            duration: track.duration,
+        })
+        .collect()
+}
+
+/// Reloads the queue saved by the last shutdown, paused so the daemon doesn't start making
+/// noise on its own. Tracks that can no longer be opened are left out.
+fn restore_queue(player: &mut Player) {
+    let Some(path) = saved_queue_path().filter(|path| path.exists()) else {
+        return;
+    };
+    let entries = match playlist::parse_m3u(&path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read saved queue {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    player.sink.pause();
+    for entry in entries.into_iter().take(player.max_queue) {
+        match open_track(&entry.path) {
+            Ok((source, metadata)) => {
+                player.append(Some(source), metadata);
+            }
+            Err(message) => warn!("Skipping {}: {}", entry.path.display(), message),
+        }
+    }
+    if !player.queue.is_empty() {
+        info!("Restored {} queued tracks, paused", player.queue.len());
+    }
+}
+
+/// Plays `--autoplay` in place of whatever was restored. A path that won't load is only
+/// logged, so the daemon still comes up and takes commands.
+fn autoplay(player: &mut Player, path: &str) {
+    match load_track(player, path, false, Placement::Play, &mut Probes::new()) {
+        Ok(response) => info!("Autoplay: {}", response.message),
+        Err(e) => error!("Failed to autoplay {}: {}", path, e),
+    }
+}
+
+async fn audio_controls(cmd: Command, player: &mut Player, mut probes: Probes) -> PlayerResult {
+    player.sync_queue();
+
+    let action = cmd.action;
+    match action {
+        Actions::Play | Actions::Queue => {
+            let tracks = match cmd.track {
+                Some(tracks) => tracks.into_vec(),
+                None => Vec::new(),
+            };
+            if tracks.is_empty() {
+                return Err(PlayerError::InvalidArgument("No track specified".into()));
+            }
+            if cmd.dry_run {
+                return check_tracks(&tracks, cmd.recursive);
+            }
+            if matches!(action, Actions::Play) && cmd.if_idle && player.is_playing() {
+                return Err(PlayerError::Refused("Already playing".into()));
+            }
+            let placement = match action {
+                Actions::Play => Placement::Play,
+                _ if cmd.next => Placement::Next,
+                _ => Placement::End,
+            };
+
+            if cmd.repeat.is_some() && !matches!(placement, Placement::Play) {
+                return Err(PlayerError::InvalidArgument(
+                    "Only play can repeat a track".into(),
+                ));
+            }
+            if cmd.start.is_some() && !matches!(placement, Placement::Play) {
+                return Err(PlayerError::InvalidArgument(
+                    "Only play can start partway into a track".into(),
+                ));
+            }
+            if cmd.start.is_some() && cmd.resume {
+                return Err(PlayerError::InvalidArgument(
+                    "Use either start or resume, not both".into(),
+                ));
+            }
+            let start = match cmd.start.map(Duration::try_from_secs_f64).transpose() {
+                Ok(start) => start,
+                Err(_) => {
+                    return Err(PlayerError::InvalidArgument(
+                        "start must be a non-negative number of seconds".into(),
+                    ));
+                }
+            };
+
+            let mut res = match tracks.as_slice() {
+                [track] => load_track(player, track, cmd.recursive, placement, &mut probes),
+                _ => load_tracks(player, &tracks, cmd.recursive, placement, &mut probes),
+            }?;
+            if let Some(times) = cmd.repeat.filter(|times| *times > 1) {
+                player.repeat_current(times);
+                res.message = format!("{} ({} times)", res.message, times);
+            }
+            if cmd.resume
+                && matches!(placement, Placement::Play)
+                && let Some(position) = player.resume_position()
+            {
+                res.message = format!("{} (resumed at {})", res.message, format_duration(position));
+                res.insert("resumed_secs", position.as_secs_f64());
+            }
+            if let Some(start) = start {
+                match player.start_at(start) {
+                    Ok(()) => {
+                        res.message = format!("{} (from {})", res.message, format_duration(start));
+                        res.insert("start_secs", start.as_secs_f64());
+                    }
+                    // Still worth playing, just not from where it was asked to start.
+                    Err(e) => {
+                        res.insert("start_secs", 0.0);
+                        res.insert(
+                            "warning",
+                            format!(
+                                "Couldn't start at {}: {}; playing from the beginning",
+                                format_duration(start),
+                                e
+                            ),
+                        );
+                    }
+                }
+            }
+            Ok(res)
+        }
+        Actions::Clear if cmd.keep_current => player.clear_upcoming(),
+        Actions::Clear => player.clear_queue(),
+        Actions::Skip => player.skip(cmd.count.unwrap_or(1)),
+        Actions::Levels => player.levels(),
+        Actions::Config => player.config(),
+        Actions::Dedup => player.dedup(cmd.by.as_deref()),
+        Actions::Restart => player.restart(),
+        Actions::Seek => match (cmd.seconds, cmd.percent) {
+            (Some(seconds), None) => player.seek(SeekTarget::Seconds(seconds)),
+            (None, Some(percent)) => player.seek(SeekTarget::Percent(percent)),
+            _ => Err(PlayerError::InvalidArgument(
+                "seek requires either a numeric 'seconds' or 'percent' field".into(),
+            )),
+        },
+        Actions::BookmarkAdd => match cmd.name {
+            Some(name) => player.add_bookmark(&name),
+            None => Err(PlayerError::InvalidArgument(
+                "No bookmark name specified".into(),
+            )),
+        },
+        Actions::BookmarkList => player.list_bookmarks(),
+        Actions::BookmarkJump => match cmd.name {
+            Some(name) => player.jump_to_bookmark(&name),
+            None => Err(PlayerError::InvalidArgument(
+                "No bookmark name specified".into(),
+            )),
+        },
+        Actions::Insert => match (cmd.index, cmd.track) {
+            (Some(0), _) if !player.queue.is_empty() => Err(PlayerError::Refused(
+                "Can't insert before the current track; use play to replace it".into(),
+            )),
+            (Some(index), Some(Tracks::One(track))) => load_track(
+                player,
+                &track,
+                cmd.recursive,
+                Placement::At(index),
+                &mut probes,
+            ),
+            _ => Err(PlayerError::InvalidArgument(
+                "insert requires an 'index' and a 'track'".into(),
+            )),
+        },
+        Actions::Forget => match cmd.track {
+            Some(Tracks::One(path)) => player.forget(&path),
+            _ => Err(PlayerError::InvalidArgument("No track specified".into())),
+        },
+        Actions::Contains => match cmd.track {
+            Some(Tracks::One(path)) => player.contains(&path),
+            _ => Err(PlayerError::InvalidArgument("No track specified".into())),
+        },
+        Actions::Sort => match cmd.key {
+            Some(key) => player.sort(&key, cmd.desc),
+            None => Err(PlayerError::InvalidArgument("No sort key specified".into())),
+        },
+        Actions::Balance => match cmd.value {
+            Some(value) => player.set_balance(value),
+            None => Err(PlayerError::InvalidArgument("No balance specified".into())),
+        },
+        Actions::Eq => player.set_eq(cmd.low, cmd.mid, cmd.high),
+        Actions::EqReset => player.reset_eq(),
+        Actions::Current => player.current(),
+        Actions::Volume => match (cmd.level, cmd.delta) {
+            (Some(level), _) => player.set_volume(level),
+            (None, Some(delta)) => player.adjust_volume(delta),
+            (None, None) => player.volume(),
+        },
+        Actions::Stop => player.stop(),
+        Actions::Status => player.status(),
+        Actions::List => player.list(),
+        Actions::Repeat => match cmd.mode {
+            Some(mode) => player.set_repeat(&mode),
+            None => Err(PlayerError::InvalidArgument(
+                "No repeat mode specified".into(),
+            )),
+        },
+        Actions::Shuffle => player.shuffle(cmd.seed),
+        Actions::Mute => player.toggle_mute(),
+        Actions::Unmute => player.set_muted(false),
+        Actions::Prev => player.prev(),
+        Actions::History => player.list_history(cmd.limit),
+        Actions::HistoryClear => player.clear_history(cmd.truncate_log),
+        Actions::Tune => player.tune(cmd.stream_chunk_kb, cmd.stream_buffer_kb, cmd.prefetch_ms),
+        Actions::Version => version(),
+        Actions::Speed => match cmd.factor {
+            Some(factor) => player.set_speed(factor, cmd.preserve_pitch),
+            None => Err(PlayerError::InvalidArgument(
+                "No speed factor specified".into(),
+            )),
+        },
+        // `handle_connection` answers pings before the player is locked.
+        Actions::Ping => Ok(PlayerResponse::new("pong")),
+        // Only `handle_connection` can stop the accept loop.
+        Actions::Shutdown => Err(PlayerError::Refused(
+            "Shutdown is handled by the connection".into(),
+        )),
+        // Subscriptions keep the connection open, so `handle_connection` deals with them.
+        Actions::Subscribe => Err(PlayerError::Refused(
+            "Subscribe must be the first command on a connection".into(),
+        )),
+        Actions::Session => Err(PlayerError::Refused(
+            "Sessions are handled by the connection".into(),
+        )),
+        Actions::FadeStop | Actions::Pause | Actions::Resume | Actions::Toggle => Err(
+            PlayerError::Refused("Fades are handled by the connection".into()),
+        ),
+        Actions::PlaylistSave => match cmd.name {
+            Some(name) => save_slot(player, &name),
+            None => Err(PlayerError::InvalidArgument("No name specified".into())),
+        },
+        Actions::PlaylistLoad => match cmd.name {
+            Some(name) => load_slot(player, &name, cmd.paused, &mut probes),
+            None => Err(PlayerError::InvalidArgument("No name specified".into())),
+        },
+        Actions::PlaylistList => list_slots(),
+        Actions::Save => match cmd.path {
+            Some(path) => save_playlist(player, Path::new(&path)),
+            None => Err(PlayerError::InvalidArgument("No path specified".into())),
+        },
+        Actions::Rename => match (cmd.index, cmd.name) {
+            (Some(index), Some(name)) => player.rename(index, &name),
+            _ => Err(PlayerError::InvalidArgument(
+                "rename requires an index and a name".into(),
+            )),
+        },
+        Actions::Remove => match cmd.index {
+            Some(index) => player.remove(index),
+            None => Err(PlayerError::InvalidArgument("No index specified".into())),
+        },
+        Actions::Move => match (cmd.from, cmd.to) {
+            (Some(from), Some(to)) => player.move_track(from, to, cmd.force),
+            _ => Err(PlayerError::InvalidArgument(
+                "Both from and to must be specified".into(),
+            )),
+        },
+        Actions::Swap => match (cmd.a, cmd.b) {
+            (Some(a), Some(b)) => player.swap(a, b),
+            _ => Err(PlayerError::InvalidArgument(
+                "Both a and b must be specified".into(),
+            )),
+        },
+        Actions::Jump => match cmd.index {
+            Some(index) => player.jump(index),
+            None => Err(PlayerError::InvalidArgument("No index specified".into())),
+        },
+    }
+}
+
+/// Build details of the running daemon, for telling which version a client is talking to.
+fn version() -> PlayerResult {
+    let version = env!("CARGO_PKG_VERSION");
+    let git_hash = option_env!("UDS_AUDIO_GIT_HASH");
+    let backend = rodio::cpal::default_host().id().name();
+    let message = match git_hash {
+        Some(hash) => format!("udsaudio {} ({}), {} backend", version, hash, backend),
+        None => format!("udsaudio {}, {} backend", version, backend),
+    };
+    Ok(PlayerResponse::new(message)
+        .with("version", version)
+        .with("git_hash", git_hash)
+        .with("backend", backend))
+}
+
+fn open_source(path: &Path) -> Result<TrackSource, String> {
+    if let Some(url) = path.to_str().filter(|track| stream::is_url(track)) {
+        return open_stream(url).map(|(source, _, _)| source);
+    }
+    // Files without an extension still get a chance to be probed.
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str())
+        && !is_supported_extension(ext)
+    {
+        return Err(format!(
+            "Unsupported format: .{} files can't be played (see `formats`)",
+            ext
+        ));
+    }
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| format!("Failed to open file: {}", e))?
+        .len();
+    // Gapless mode trims the encoder delay and padding that MP3 and AAC add around the
+    // audio, which is what would otherwise put a click or gap between tracks of a
+    // continuous mix.
+    let mut builder = rodio::decoder::DecoderBuilder::new()
+        .with_byte_len(len)
+        .with_seekable(true)
+        .with_gapless(true);
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        builder = builder.with_hint(ext);
+    }
+    let source = builder
+        .with_data(BufReader::new(file))
+        .build()
+        .map_err(|e| format!("Failed to decode audio, the file may be corrupt: {}", e))?;
+    Ok(Box::new(source))
+}
+
+/// Opens a stream, returning it with the station name and codec it announced.
+fn open_stream(url: &str) -> Result<(TrackSource, Option<String>, Option<&'static str>), String> {
+    let stream = stream::HttpStream::open(url)?;
+    let name = stream.name.clone();
+    let codec = stream
+        .content_type
+        .as_deref()
+        .and_then(mime_codec)
+        .or_else(|| codec_name(Path::new(url)));
+    let mut builder = rodio::decoder::DecoderBuilder::new().with_seekable(false);
+    if let Some(length) = stream.content_length {
+        builder = builder.with_byte_len(length);
+    }
+    if let Some(mime_type) = &stream.content_type {
+        builder = builder.with_mime_type(mime_type);
+    }
+    if let Some(ext) = Path::new(url).extension().and_then(|ext| ext.to_str()) {
+        builder = builder.with_hint(ext);
+    }
+    let source = builder
+        .with_data(stream)
+        .build()
+        .map_err(|e| format!("Failed to decode stream: {}", e))?;
+    Ok((Box::new(source), name, codec))
+}
+
+/// The codec a file's extension stands for, as listed by `formats`.
+fn codec_name(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    SUPPORTED_FORMATS
+        .iter()
+        .find(|(supported, _)| *supported == ext)
+        .map(|(_, codec)| *codec)
+}
+
+/// The codec a stream's `Content-Type` stands for, ignoring parameters such as `charset`.
+fn mime_codec(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    match mime.as_str() {
+        "audio/mpeg" | "audio/mp3" => Some("MP3"),
+        "audio/flac" | "audio/x-flac" => Some("FLAC"),
+        "audio/wav" | "audio/x-wav" | "audio/wave" => Some("PCM (WAV)"),
+        "audio/ogg" | "audio/vorbis" | "application/ogg" => Some("Vorbis"),
+        "audio/mp4" | "audio/x-m4a" => Some("AAC (MP4)"),
+        "audio/aac" | "audio/aacp" => Some("AAC (ADTS)"),
+        _ => None,
+    }
+}
+
+/// Uses the already-decoded source if it belongs to `track`, otherwise opens the file again.
+fn take_or_open(
+    track: &TrackInfo,
+    decoded: &mut Option<(u64, TrackSource)>,
+) -> Result<TrackSource, String> {
+    match decoded.take() {
+        Some((id, source)) if id == track.id => Ok(source),
+        other => {
+            *decoded = other;
+            open_source(Path::new(&track.path))
+        }
+    }
+}
+
+fn open_track(path: &Path) -> Result<(TrackSource, TrackInfo), String> {
+    if let Some(url) = path.to_str().filter(|track| stream::is_url(track)) {
+        let (source, name, codec) = open_stream(url)?;
+        let metadata = TrackInfo {
+            id: 0,
+            name: name.unwrap_or_else(|| url.to_string()),
+            path: url.to_string(),
+            title: None,
+            artist: None,
+            album: None,
+            duration: source.total_duration(),
+            codec,
+            replay_gain: tags::ReplayGain::default(),
+        };
+        return Ok((source, metadata));
+    }
+
+    let source = open_source(path)?;
+    let duration = source.total_duration();
+    let tags = tags::read_tags(path);
+    let metadata = TrackInfo {
+        id: 0,
+        name: tags.display_name(path),
+        path: path.display().to_string(),
+        title: tags.title,
+        artist: tags.artist,
+        album: tags.album,
+        duration,
+        codec: codec_name(path),
+        replay_gain: tags.replay_gain,
+    };
+    Ok((source, metadata))
+}
+
+/// Reads a track's details and checks that it decodes, without keeping it open; the sink
+/// opens it again once it's due. Streams aren't connected to until then, so they're assumed
+/// playable, as `check_track` does.
+fn probe_track(path: &Path) -> Result<TrackInfo, String> {
+    if let Some(url) = path.to_str().filter(|track| stream::is_url(track)) {
+        return Ok(TrackInfo {
+            id: 0,
+            name: url.to_string(),
+            path: url.to_string(),
+            title: None,
+            artist: None,
+            album: None,
+            duration: None,
+            codec: codec_name(path),
+            replay_gain: tags::ReplayGain::default(),
+        });
+    }
+    open_track(path).map(|(_, metadata)| metadata)
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(is_supported_extension)
+}
+
+fn is_supported_extension(ext: &str) -> bool {
+    let ext = ext.to_ascii_lowercase();
+    SUPPORTED_FORMATS
+        .iter()
+        .any(|(supported, _)| *supported == ext)
+}
+
+/// Collects audio files under `dir`, sorted by path, along with any files that were skipped
+/// for not looking like audio.
+fn audio_files(dir: &Path, recursive: bool) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if is_audio_file(&path) {
+                files.push(path);
+            } else {
+                skipped.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    skipped.sort();
+    Ok((files, skipped))
+}
+
+/// Resolves a file, directory, or playlist the way `load_track` would and reports whether
+/// each track in it could be played, without touching the queue. Streams aren't connected
+/// to, so they're assumed playable.
+fn check_track(track: &str, recursive: bool) -> PlayerResult {
+    let track = stream::local_path(track).map_err(PlayerError::InvalidArgument)?;
+    let track = track.as_ref();
+    let path = Path::new(track);
+    let mut skipped = Vec::new();
+    let paths = if path.is_dir() {
+        match audio_files(path, recursive) {
+            Ok((files, non_audio)) => {
+                skipped = non_audio;
+                files
+            }
+            Err(e) => {
+                return Err(PlayerError::Playback(format!(
+                    "Failed to read directory: {}",
+                    e
+                )));
+            }
+        }
+    } else if !stream::is_url(track) && playlist::is_playlist(path) {
+        match playlist::parse(path) {
+            Ok(entries) => entries.into_iter().map(|entry| entry.path).collect(),
+            Err(e) => {
+                return Err(PlayerError::Playback(format!(
+                    "Failed to read playlist: {}",
+                    e
+                )));
+            }
+        }
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let tracks: Vec<Value> = paths
+        .iter()
+        .map(|path| {
+            let url = path.to_str().is_some_and(stream::is_url);
+            let error = match url {
+                true => None,
+                false => open_source(path).err(),
+            };
+            json!({
+                "path": path.display().to_string(),
+                "playable": error.is_none(),
+                "reason": error
+            })
+        })
+        .collect();
+    let playable = tracks
+        .iter()
+        .filter(|track| track["playable"] == true)
+        .count();
+    let skipped: Vec<String> = skipped.iter().map(|p| p.display().to_string()).collect();
+    Ok(PlayerResponse::new(format!(
+        "{} of {} track(s) playable",
+        playable,
+        tracks.len()
+    ))
+    .with("tracks", tracks)
+    .with("skipped", skipped))
+}
+
+/// Runs `check_track` on each of `tracks` and combines the results into one report.
+fn check_tracks(tracks: &[String], recursive: bool) -> PlayerResult {
+    if let [track] = tracks {
+        return check_track(track, recursive);
+    }
+
+    let mut checked = Vec::new();
+    let mut skipped = Vec::new();
+    for track in tracks {
+        let mut res = match check_track(track, recursive) {
+            Ok(res) => res,
+            Err(e) => {
+                checked.push(json!({
+                    "path": track,
+                    "playable": false,
+                    "reason": e.message()
+                }));
+                continue;
+            }
+        };
+        if let Some(Value::Array(found)) = res.remove("tracks") {
+            checked.extend(found);
+        }
+        if let Some(Value::Array(found)) = res.remove("skipped") {
+            skipped.extend(found);
+        }
+    }
+    let playable = checked
+        .iter()
+        .filter(|track| track["playable"] == true)
+        .count();
+    Ok(PlayerResponse::new(format!(
+        "{} of {} track(s) playable",
+        playable,
+        checked.len()
+    ))
+    .with("tracks", checked)
+    .with("skipped", skipped))
+}
+
+/// Loads several tracks in one go, in the order given, and summarises how each went. A
+/// failure doesn't stop the rest; with `Placement::Play`, the first one that loads replaces
+/// the queue and the others are queued after it.
+fn load_tracks(
+    player: &mut Player,
+    tracks: &[String],
+    recursive: bool,
+    placement: Placement,
+    probes: &mut Probes,
+) -> PlayerResult {
+    let mut results = Vec::with_capacity(tracks.len());
+    let requested = placement;
+    let mut placement = placement;
+    let mut loaded = 0;
+    // Each insertion after the current track lands in front of the previous one, so go
+    // backwards to keep the order.
+    let ordered: Vec<&String> = match placement {
+        Placement::Next => tracks.iter().rev().collect(),
+        _ => tracks.iter().collect(),
+    };
+    for track in ordered {
+        let (succeeded, message) = match load_track(player, track, recursive, placement, probes) {
+            Ok(res) => {
+                loaded += res.get("queued").and_then(Value::as_u64).unwrap_or(1);
+                if matches!(placement, Placement::Play | Placement::Cue) {
+                    placement = Placement::End;
+                }
+                (true, res.message)
+            }
+            Err(e) => (false, e.to_string()),
+        };
+        results.push(json!({
+            "track": track,
+            "status": succeeded,
+            "message": message
+        }));
+    }
+    if matches!(requested, Placement::Next) {
+        results.reverse();
+    }
+
+    let failed = results.iter().filter(|res| res["status"] != true).count();
+    let mut message = match requested {
+        _ if loaded == 0 => "Nothing could be loaded".to_string(),
+        Placement::Play => format!("Now playing {} track(s)", loaded),
+        Placement::Cue => format!("Loaded {} track(s), paused", loaded),
+        Placement::Next => format!("Queued {} track(s) to play next", loaded),
+        Placement::End => format!("Added {} track(s) to the queue", loaded),
+        Placement::At(index) => format!("Inserted {} track(s) at position {}", loaded, index),
+    };
+    if failed > 0 {
+        message.push_str(&format!("; {} of {} failed", failed, tracks.len()));
+    }
+    if loaded == 0 {
+        return Err(PlayerError::Playback(message)
+            .with("queued", loaded)
+            .with("results", results));
+    }
+    Ok(PlayerResponse::new(message)
+        .with("queued", loaded)
+        .with("results", results))
+}
+
+/// Opens a file, directory, playlist, or stream and plays or queues what it contains.
+fn load_track(
+    player: &mut Player,
+    track: &str,
+    recursive: bool,
+    placement: Placement,
+    probes: &mut Probes,
+) -> PlayerResult {
+    if player.room(placement) == 0 {
+        return Err(PlayerError::QueueFull(format!(
+            "Queue is full ({} tracks); 0 slots remain",
+            player.max_queue
+        ))
+        .with("remaining_slots", 0));
+    }
+    let track = stream::local_path(track).map_err(PlayerError::InvalidArgument)?;
+    let track = track.as_ref();
+    let path = Path::new(track);
+    if path.is_dir() {
+        let probed = match probes.remove(path) {
+            Some(probed) => probed,
+            None => probe_directory(path, recursive, player.room(placement)),
+        }?;
+        return enqueue_directory(player, path, probed, placement);
+    }
+    if is_local_playlist(path) {
+        let probed = match probes.remove(path) {
+            Some(probed) => probed,
+            None => probe_playlist(path, player.room(placement)),
+        }?;
+        return enqueue_playlist(player, path, probed, placement);
+    }
+
+    let (source, metadata) = match open_track(Path::new(track)) {
+        Ok(t) => t,
+        Err(message) => {
+            return Err(PlayerError::Playback(message));
+        }
+    };
+    match placement {
+        Placement::Play => player.play(source, metadata),
+        Placement::Cue => player.cue(source, metadata),
+        Placement::Next => player.play_next(source, metadata),
+        Placement::End => player.push_to_queue(source, metadata),
+        Placement::At(index) => player.insert_at(index, source, metadata),
+    }
+}
+
+/// What a directory or playlist holds, probed before the player is locked so reading the
+/// files doesn't hold up other clients.
+struct Probed {
+    tracks: Vec<TrackInfo>,
+    failed: Vec<Value>,
+    dropped: usize,
+    skipped: Vec<PathBuf>,
+}
+
+/// Probes of the directories and playlists a request names, keyed by their path.
+type Probes = HashMap<PathBuf, Result<Probed, PlayerError>>;
+
+/// Probes the directories and playlists a `play`, `queue`, `insert`, or `playlist-load` names
+/// on a blocking thread, so the player is only locked to queue what they hold.
+async fn probe_request(cmd: &Command, player: &Mutex<Player>) -> Probes {
+    let (tracks, placement, recursive) = match (cmd.action, &cmd.track, &cmd.name) {
+        (Actions::Play | Actions::Queue | Actions::Insert, Some(tracks), _) if !cmd.dry_run => {
+            let placement = match cmd.action {
+                Actions::Play => Placement::Play,
+                Actions::Insert => Placement::At(cmd.index.unwrap_or_default()),
+                _ if cmd.next => Placement::Next,
+                _ => Placement::End,
+            };
+            let tracks = match tracks {
+                Tracks::One(track) => vec![track.clone()],
+                Tracks::Many(tracks) => tracks.clone(),
+            };
+            (tracks, placement, cmd.recursive)
+        }
+        (Actions::PlaylistLoad, _, Some(name)) => match slot_path(name) {
+            Ok(path) => (vec![path.display().to_string()], Placement::Play, false),
+            Err(_) => return Probes::new(),
+        },
+        _ => return Probes::new(),
+    };
+    let paths: Vec<PathBuf> = tracks
+        .iter()
+        .filter_map(|track| stream::local_path(track).ok())
+        .map(|track| PathBuf::from(track.as_ref()))
+        .filter(|path| path.is_dir() || is_local_playlist(path))
+        .collect();
+    if paths.is_empty() {
+        return Probes::new();
+    }
+
+    // The room is checked again once the player is locked; this only bounds the probing.
+    let room = player.lock().await.room(placement);
+    let probed = tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|path| {
+                let probed = match path.is_dir() {
+                    true => probe_directory(&path, recursive, room),
+                    false => probe_playlist(&path, room),
+                };
+                (path, probed)
+            })
+            .collect()
+    })
+    .await;
+    probed.unwrap_or_default()
+}
+
+/// Whether `path` is a playlist file rather than a stream. Remote playlists aren't fetched;
+/// a URL is always played as a stream.
+fn is_local_playlist(path: &Path) -> bool {
+    !path.to_str().is_some_and(stream::is_url) && playlist::is_playlist(path)
+}
+
+/// Finds the audio files in `dir` and probes up to `room` of them.
+fn probe_directory(dir: &Path, recursive: bool, room: usize) -> Result<Probed, PlayerError> {
+    let (files, skipped) = match audio_files(dir, recursive) {
+        Ok(found) => found,
+        Err(e) => {
+            return Err(PlayerError::Playback(format!(
+                "Failed to read directory: {}",
+                e
+            )));
+        }
+    };
+
+    if files.is_empty() {
+        return Err(PlayerError::NotFound(format!(
+            "No audio files found in {}",
+            dir.display()
+        )));
+    }
+
+    // Check everything up front; files that won't open are reported instead of stopping
+    // the rest from loading.
+    let mut tracks = Vec::with_capacity(files.len().min(room));
+    let mut failed = Vec::new();
+    let mut dropped = 0;
+    for file in &files {
+        if tracks.len() == room {
+            dropped += 1;
+            continue;
+        }
+        match probe_track(file) {
+            Ok(track) => tracks.push(track),
+            Err(reason) => failed.push(failed_entry(file, reason)),
+        }
+    }
+    if tracks.is_empty() {
+        return Err(PlayerError::Playback(format!(
+            "None of the audio files in {} could be loaded",
+            dir.display()
+        ))
+        .with("failed", failed));
+    }
+    Ok(Probed {
+        tracks,
+        failed,
+        dropped,
+        skipped,
+    })
+}
+
+/// Reads the playlist at `path` and probes up to `room` of its entries.
+fn probe_playlist(path: &Path, room: usize) -> Result<Probed, PlayerError> {
+    let entries = match playlist::parse(path) {
+        Ok(entries) => entries,
         Err(e) => {
-            let res = json!({"status":false,"message":format!("{} \nPlease make sure that daemon is running.",e)});
-            return res;
+            return Err(PlayerError::Playback(format!(
+                "Failed to read playlist: {}",
+                e
+            )));
         }
     };
 
-    let _ = match Actions::from_str(action) {
-        Some(a) => a,
-        None => {
-            return json!({
+    if entries.is_empty() {
+        return Err(PlayerError::NotFound(format!(
+            "No tracks found in {}",
+            path.display()
+        )));
+    }
+
+    let mut tracks = Vec::with_capacity(entries.len().min(room));
+    let mut failed = Vec::new();
+    let mut dropped = 0;
+    for entry in entries {
+        if tracks.len() == room {
+            dropped += 1;
+            continue;
+        }
+        match probe_track(&entry.path) {
+            Ok(mut metadata) => {
+                // Embedded tags beat the playlist's idea of the title.
+                if let (None, Some(title)) = (&metadata.title, entry.title) {
+                    metadata.name = title;
+                }
+                if metadata.duration.is_none() {
+                    metadata.duration = entry.duration;
+                }
+                tracks.push(metadata);
+            }
+            Err(reason) => failed.push(failed_entry(&entry.path, reason)),
+        }
+    }
+    if tracks.is_empty() {
+        return Err(PlayerError::Playback(format!(
+            "None of the tracks in {} could be loaded",
+            path.display()
+        ))
+        .with("failed", failed));
+    }
+    Ok(Probed {
+        tracks,
+        failed,
+        dropped,
+        skipped: Vec::new(),
+    })
+}
+
+/// Drops whatever no longer fits the queue, which may have filled up while `probed` was
+/// being read.
+fn fit_to_room(player: &Player, probed: &mut Probed, placement: Placement) {
+    let room = player.room(placement);
+    if probed.tracks.len() > room {
+        probed.dropped += probed.tracks.len() - room;
+        probed.tracks.truncate(room);
+    }
+}
+
+fn enqueue_directory(
+    player: &mut Player,
+    dir: &Path,
+    mut probed: Probed,
+    placement: Placement,
+) -> PlayerResult {
+    fit_to_room(player, &mut probed, placement);
+    let Probed {
+        tracks,
+        failed,
+        dropped,
+        skipped,
+    } = probed;
+
+    let count = tracks.len();
+    let mut message = enqueue_tracks(player, tracks, placement, dir)?;
+    if !failed.is_empty() {
+        message.push_str(&format!("; {} file(s) failed to load", failed.len()));
+    }
+    if dropped > 0 {
+        message.push_str(&format!("; dropped {} over the queue limit", dropped));
+    }
+    if !skipped.is_empty() {
+        message.push_str(&format!("; skipped {} non-audio file(s)", skipped.len()));
+    }
+    let skipped: Vec<String> = skipped.iter().map(|p| p.display().to_string()).collect();
+    Ok(PlayerResponse::new(message)
+        .with("queued", count)
+        .with("dropped", dropped)
+        .with("failed", failed)
+        .with("skipped", skipped))
+}
+
+fn enqueue_playlist(
+    player: &mut Player,
+    path: &Path,
+    mut probed: Probed,
+    placement: Placement,
+) -> PlayerResult {
+    fit_to_room(player, &mut probed, placement);
+    let Probed {
+        tracks,
+        failed,
+        dropped,
+        ..
+    } = probed;
+
+    let count = tracks.len();
+    let mut message = enqueue_tracks(player, tracks, placement, path)?;
+    if !failed.is_empty() {
+        message.push_str(&format!("; {} track(s) failed to load", failed.len()));
+    }
+    if dropped > 0 {
+        message.push_str(&format!("; dropped {} over the queue limit", dropped));
+    }
+    Ok(PlayerResponse::new(message)
+        .with("queued", count)
+        .with("dropped", dropped)
+        .with("failed", failed))
+}
+
+/// A file left out of a directory or playlist, with the reason it couldn't be opened.
+fn failed_entry(path: &Path, reason: String) -> Value {
+    json!({
+        "path": path.display().to_string(),
+        "reason": reason
+    })
+}
+
+/// Plays or queues probed tracks in order and describes what happened. Only the track that
+/// starts playing is opened here; the rest wait for the sink to need them. When playing, an
+/// error from the player is handed back unchanged.
+fn enqueue_tracks(
+    player: &mut Player,
+    tracks: Vec<TrackInfo>,
+    placement: Placement,
+    origin: &Path,
+) -> Result<String, PlayerError> {
+    let count = tracks.len();
+    let mut tracks = tracks.into_iter();
+    match placement {
+        Placement::Play | Placement::Cue => {
+            let metadata = tracks.next().unwrap();
+            let source = open_source(Path::new(&metadata.path)).map_err(PlayerError::Playback)?;
+            let name = metadata.name.clone();
+            let started = match placement {
+                Placement::Cue => player.cue(source, metadata).map(|_| "Paused on")?,
+                _ => player.play(source, metadata).map(|_| "Now playing")?,
+            };
+            for metadata in tracks {
+                player.append(None, metadata);
+            }
+            Ok(format!(
+                "{} {} ({} tracks from {})",
+                started,
+                name,
+                count,
+                origin.display()
+            ))
+        }
+        Placement::Next => {
+            for (offset, metadata) in tracks.enumerate() {
+                player.insert(1 + offset, None, metadata);
+            }
+            Ok(format!(
+                "Queued {} tracks from {} to play next",
+                count,
+                origin.display()
+            ))
+        }
+        Placement::End => {
+            for metadata in tracks {
+                player.append(None, metadata);
+            }
+            Ok(format!(
+                "Added {} tracks from {} to the queue",
+                count,
+                origin.display()
+            ))
+        }
+        Placement::At(index) => {
+            let index = index.min(player.queue.len());
+            for (offset, metadata) in tracks.enumerate() {
+                player.insert(index + offset, None, metadata);
+            }
+            Ok(format!(
+                "Inserted {} tracks from {} at position {}",
+                count,
+                origin.display(),
+                index
+            ))
+        }
+    }
+}
+
+/// Shuffles `items` into a random order, the same one every time for the same `seed`.
+fn shuffle<T>(items: &mut [T], seed: Option<u64>) {
+    match seed {
+        Some(seed) => items.shuffle(&mut StdRng::seed_from_u64(seed)),
+        None => items.shuffle(&mut rand::rng()),
+    }
+}
+
+/// A connection to the daemon, buffered so responses can be read one line at a time.
+type Connection = tokio::io::BufReader<Box<dyn socket::Stream>>;
+
+/// Where the CLI sends commands, and the secret to prove it's allowed to.
+struct Client {
+    endpoint: Endpoint,
+    token: Option<String>,
+    retries: u32,
+    retry_delay: Duration,
+    // Set for the REPL, which sends everything over one connection.
+    session: Option<Mutex<Connection>>,
+}
+
+impl Client {
+    /// Connects to the daemon. A script that starts the daemon and sends a command straight
+    /// away can get there before the socket is bound, so a missing or refusing socket is
+    /// retried `retries` times, backing off between attempts.
+    async fn connect(&self) -> std::io::Result<Box<dyn socket::Stream>> {
+        let mut delay = self.retry_delay;
+        let mut attempts = 0;
+        loop {
+            match self.endpoint.connect().await {
+                Err(e)
+                    if attempts < self.retries
+                        && matches!(
+                            e.kind(),
+                            std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                        ) =>
+                {
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connects once the daemon has answered a `ping`, so a daemon that isn't running is
+    /// told apart from one that's stuck. Fails with the response to show when it hasn't.
+    async fn connect_live(&self) -> Result<Box<dyn socket::Stream>, Value> {
+        let not_running = |e: std::io::Error| {
+            json!({
                 "status": false,
-                "message": format!("Invalid action: {}", action)
-            });
+                "message": format!("Daemon is not running at {}: {}", self.endpoint, e)
+            })
+        };
+        let mut probe = tokio::io::BufReader::new(self.connect().await.map_err(not_running)?);
+        let ping = exchange(&mut probe, self.request(Actions::Ping.request(json!({}))));
+        match tokio::time::timeout(PING_TIMEOUT, ping).await {
+            Ok(pong) if pong["status"] == true => {}
+            Ok(pong) => return Err(pong),
+            Err(_) => {
+                return Err(json!({
+                    "status": false,
+                    "message": format!(
+                        "Daemon at {} is not responding (no answer to ping within {}s)",
+                        self.endpoint,
+                        PING_TIMEOUT.as_secs()
+                    )
+                }));
+            }
+        }
+        self.connect().await.map_err(not_running)
+    }
+
+    /// Frames `cmd` as one request line, adding the token when there is one.
+    fn request(&self, mut cmd: Value) -> String {
+        if let Some(token) = &self.token {
+            cmd["token"] = json!(token);
+        }
+        format!("{}\n", cmd)
+    }
+
+    /// Opens a connection that the daemon keeps up between commands. Fails with the
+    /// daemon's response when it can't be set up.
+    async fn open_session(&self) -> Result<Client, Value> {
+        let stream = self.connect_live().await?;
+        let mut session = tokio::io::BufReader::new(stream);
+        let ack = exchange(
+            &mut session,
+            self.request(Actions::Session.request(json!({}))),
+        )
+        .await;
+        if ack["status"] != true {
+            return Err(ack);
+        }
+        Ok(Client {
+            endpoint: self.endpoint.clone(),
+            token: self.token.clone(),
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+            session: Some(Mutex::new(session)),
+        })
+    }
+}
+
+async fn send_command(socket: &Client, cmd: Value) -> Value {
+    if let Some(session) = &socket.session {
+        return exchange(&mut *session.lock().await, socket.request(cmd)).await;
+    }
+    let mut stream = match socket.connect_live().await {
+        Ok(stream) => tokio::io::BufReader::new(stream),
+        Err(res) => return res,
+    };
+
+    exchange(&mut stream, socket.request(cmd)).await
+}
+
+/// Sends one request and reads the single line that answers it. Nothing past that line is
+/// read, so the connection can carry another request, and a daemon that keeps the socket
+/// open afterwards doesn't leave the client waiting.
+async fn exchange(connection: &mut Connection, request: String) -> Value {
+    if let Err(e) = connection.write_all(request.as_bytes()).await {
+        return json!({
+            "status": false,
+            "message": format!("Failed to send command to daemon: {}", e)
+        });
+    }
+    let mut line = String::new();
+    match connection.read_line(&mut line).await {
+        Ok(0) => json!({
+            "status": false,
+            "message": "The daemon closed the connection"
+        }),
+        Ok(_) => parse_response(&line),
+        Err(e) => json!({
+            "status": false,
+            "message": format!("Failed to read response from daemon: {}", e)
+        }),
+    }
+}
+
+fn parse_response(res_str: &str) -> Value {
+    match serde_json::from_str(res_str) {
+        Ok(res) => res,
+        Err(e) => json!({
+            "status": false,
+            "message": format!("Invalid response from daemon: {}", e)
+        }),
+    }
+}
+
+/// One line typed into the REPL, parsed with the same subcommands as the command line.
+#[derive(Parser)]
+#[command(name = "udsaudio", no_binary_name = true, disable_version_flag = true)]
+struct ReplLine {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// Reads commands from a prompt and runs them over one connection until `quit` or Ctrl-D.
+/// History is kept between runs next to the daemon's state.
+async fn repl(socket: &Client, json: bool) -> bool {
+    let session = match socket.open_session().await {
+        Ok(session) => session,
+        Err(res) => return colored_print(res, json),
+    };
+    let mut editor = match rustyline::DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            return colored_print(
+                json!({"status": false, "message": format!("Failed to start the prompt: {}", e)}),
+                json,
+            );
         }
     };
+    let history = state_dir().map(|dir| dir.join(REPL_HISTORY_FILE_NAME));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match tokio::task::block_in_place(|| editor.readline("udsaudio> ")) {
+            Ok(line) => line,
+            // Ctrl-C drops the line being typed, like a shell does.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", format!("Failed to read input: {}", e).red());
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        if matches!(line, "quit" | "exit") {
+            break;
+        }
+        let words = match split_words(line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{}", e.red());
+                continue;
+            }
+        };
+        match ReplLine::try_parse_from(words) {
+            Ok(ReplLine {
+                command: Commands::Subscribe,
+            }) => eprintln!(
+                "{}",
+                "subscribe never returns; run it from another shell instead".red()
+            ),
+            Ok(ReplLine { command }) => {
+                run_client(command, &session, json).await;
+            }
+            Err(e) => {
+                let _ = e.print();
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Some(dir) = history.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = editor.save_history(history);
+    }
+    true
+}
 
-    let cmd = json!({"action":action,"track":track});
-    stream.write_all(cmd.to_string().as_bytes()).await.unwrap();
-    let mut buf = Vec::new();
-    stream.read_to_end(&mut buf).await.unwrap();
+/// Splits a line into words the way a shell would: quotes keep spaces inside a word and a
+/// backslash takes the next character literally (except inside single quotes).
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => match chars.next() {
+                Some(next) => word.get_or_insert_default().push(next),
+                None => return Err("Nothing to escape after the trailing backslash".to_string()),
+            },
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("Unterminated quote".to_string());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Prints events from the daemon as they arrive, one JSON object per line. Returns whether
+/// the subscription was set up.
+async fn subscribe(socket: &Client, request: Value, json: bool) -> bool {
+    let mut stream = match socket.connect_live().await {
+        Ok(stream) => stream,
+        Err(res) => return colored_print(res, json),
+    };
+    if let Err(e) = stream.write_all(socket.request(request).as_bytes()).await {
+        return colored_print(
+            json!({
+                "status": false,
+                "message": format!("Failed to send command to daemon: {}", e)
+            }),
+            json,
+        );
+    }
 
-    let res_str = String::from_utf8_lossy(&buf).to_string();
-    serde_json::from_str(&res_str).unwrap()
+    let mut lines = tokio::io::BufReader::new(stream).lines();
+    match lines.next_line().await {
+        Ok(Some(ack)) => match serde_json::from_str(&ack) {
+            Ok(res) => {
+                if !colored_print(res, json) {
+                    return false;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", format!("Invalid response from daemon: {}", e).red());
+                return false;
+            }
+        },
+        _ => {
+            eprintln!("{}", "Daemon closed the connection".red());
+            return false;
+        }
+    }
+    while let Ok(Some(event)) = lines.next_line().await {
+        println!("{}", event);
+    }
+    true
 }
 
-fn colored_print(res: Value) {
-    let response: Response = serde_json::from_value(res.clone()).unwrap();
-    if response.status == true {
-        println!("{}", response.message.blue());
+/// Prints the daemon's response and returns its `status`. Failures go to stderr so stdout
+/// only carries results.
+fn colored_print(res: Value, json: bool) -> bool {
+    if json {
+        println!("{}", res);
+        return res["status"] == true;
+    }
+    let response: Response = match serde_json::from_value(res) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", format!("Malformed response from daemon: {}", e).red());
+            return false;
+        }
+    };
+    if response.status {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!("{}", response.message.blue());
+        }
     } else {
-        println!("{}", response.message.red())
+        eprintln!("{}", response.message.red())
+    }
+    response.status
+}
+
+/// Sends a single track as a plain string, so older daemons still understand it.
+fn track_field(tracks: &[String]) -> Value {
+    match tracks {
+        [track] => json!(track),
+        _ => json!(tracks),
+    }
+}
+
+/// Prints a `play`/`queue` response followed by any tracks that failed to load.
+fn print_enqueued(res: Value, json: bool) -> bool {
+    if json {
+        return colored_print(res, json);
+    }
+    let mut failed: Vec<(String, String)> = Vec::new();
+    for entry in res["failed"].as_array().into_iter().flatten() {
+        let path = entry["path"].as_str().unwrap_or_default();
+        let reason = entry["reason"].as_str().unwrap_or_default();
+        failed.push((path.to_string(), reason.to_string()));
+    }
+    for result in res["results"].as_array().into_iter().flatten() {
+        if result["status"] != true {
+            let track = result["track"].as_str().unwrap_or_default();
+            let message = result["message"].as_str().unwrap_or_default();
+            failed.push((track.to_string(), message.to_string()));
+        }
+    }
+
+    let warning = res["warning"].as_str().map(str::to_string);
+    let succeeded = colored_print(res, json);
+    if let Some(warning) = warning {
+        eprintln!("{}", warning.yellow());
+    }
+    for (path, reason) in &failed {
+        eprintln!("{}", format!("  {}: {}", path, reason).yellow());
+    }
+    succeeded
+}
+
+/// Prints each track a dry run found, green if it can be played and red with the reason if not.
+fn print_dry_run(res: Value, json: bool) -> bool {
+    let tracks = match res["tracks"].as_array() {
+        Some(tracks) if res["status"] == true && !json => tracks.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for track in &tracks {
+        let path = track["path"].as_str().unwrap_or_default();
+        match track["reason"].as_str() {
+            None => println!("{}", format!("  {}", path).green()),
+            Some(reason) => println!("{}", format!("  {}: {}", path, reason).red()),
+        }
+    }
+    if let Some(skipped) = res["skipped"].as_array()
+        && !skipped.is_empty()
+    {
+        println!("Skipped {} non-audio file(s)", skipped.len());
+    }
+    true
+}
+
+/// Draws each channel's level as a bar, RMS filled in and the peak marked.
+fn print_levels(res: Value, json: bool) -> bool {
+    const WIDTH: usize = 40;
+    let channels = match res["channels"].as_array() {
+        Some(channels) if res["status"] == true && !json => channels.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for (index, channel) in channels.iter().enumerate() {
+        let width = |field: &str| {
+            let level = channel[field].as_f64().unwrap_or_default().clamp(0.0, 1.0);
+            (level * WIDTH as f64).round() as usize
+        };
+        let (rms, peak) = (width("rms"), width("peak").max(width("rms")));
+        let bar = format!(
+            "{}{}{}",
+            "#".repeat(rms),
+            "-".repeat(peak - rms),
+            " ".repeat(WIDTH - peak)
+        );
+        println!("{:>3} [{}]", index + 1, bar.green());
+    }
+    true
+}
+
+fn print_bookmarks(res: Value, json: bool) -> bool {
+    let bookmarks = match res["bookmarks"].as_array() {
+        Some(bookmarks) if res["status"] == true && !json => bookmarks.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for bookmark in &bookmarks {
+        let name = bookmark["name"].as_str().unwrap_or_default();
+        let position = bookmark["position"].as_str().unwrap_or_default();
+        println!("  {:>8}  {}", position, name);
+    }
+    true
+}
+
+fn print_playlists(res: Value, json: bool) -> bool {
+    let playlists = match res["playlists"].as_array() {
+        Some(playlists) if res["status"] == true && !json => playlists.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for playlist in &playlists {
+        let name = playlist["name"].as_str().unwrap_or_default();
+        let tracks = playlist["tracks"].as_u64().unwrap_or_default();
+        println!("  {:>5}  {}", tracks, name);
+    }
+    true
+}
+
+fn print_history(res: Value, json: bool) -> bool {
+    let history = match res["history"].as_array() {
+        Some(history) if res["status"] == true && !json => history.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    let now = unix_secs(SystemTime::now());
+    for track in &history {
+        let name = track["name"].as_str().unwrap_or_default();
+        let ago = now.saturating_sub(track["timestamp"].as_u64().unwrap_or(now));
+        println!("  {:>8}  {}", format_ago(ago), name);
+    }
+    true
+}
+
+/// "45s ago", "12m ago", "3h ago", or "2d ago", whichever unit fits.
+fn format_ago(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn print_config(res: Value, json: bool) -> bool {
+    let settings = match res.as_object() {
+        Some(settings) if res["status"] == true && !json => settings.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for (key, value) in settings {
+        if key == "status" || key == "message" {
+            continue;
+        }
+        let value = match value {
+            Value::String(value) => value.clone(),
+            Value::Null => "-".to_string(),
+            value => value.to_string(),
+        };
+        println!("  {}: {}", key, value);
+    }
+    true
+}
+
+fn print_queue(res: Value, json: bool) -> bool {
+    let tracks = match res["tracks"].as_array() {
+        Some(tracks) if res["status"] == true && !json => tracks.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for track in &tracks {
+        let index = track["index"].as_u64().unwrap_or_default();
+        let name = track["name"].as_str().unwrap_or_default();
+        let duration = match track["duration_secs"].is_number() {
+            true => format_duration(secs_field(&track["duration_secs"])),
+            false => "live".to_string(),
+        };
+        let line = format!("{:>3}. {} ({})", index, name, duration);
+        if index == 0 {
+            println!("{}", line.green());
+        } else {
+            println!("{}", line);
+        }
+    }
+    if res["total_duration_secs"].is_number() && !tracks.is_empty() {
+        let total = secs_field(&res["total_duration_secs"]);
+        println!("Total: {}", format_duration(total));
+        let remaining = match res["total_remaining_secs"].is_number() {
+            true => format_hms(secs_field(&res["total_remaining_secs"])),
+            false => "unknown".to_string(),
+        };
+        println!("Remaining: {}", remaining);
+    }
+    true
+}
+
+fn list_devices() -> Value {
+    let names = devices::output_device_names();
+    let default = devices::default_output_device_name();
+    if names.is_empty() {
+        return json!({
+            "status": false,
+            "message": "No audio output devices found"
+        });
+    }
+    json!({
+        "status": true,
+        "message": format!("{} output device(s)", names.len()),
+        "devices": names,
+        "default": default
+    })
+}
+
+fn print_devices(res: Value, json: bool) -> bool {
+    let devices = match res["devices"].as_array() {
+        Some(devices) if res["status"] == true && !json => devices.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for device in &devices {
+        let name = device.as_str().unwrap_or_default();
+        if res["default"].as_str() == Some(name) {
+            println!("{}", format!("{} (default)", name).green());
+        } else {
+            println!("{}", name);
+        }
+    }
+    true
+}
+
+/// Reads a `*_secs` field from a response, treating anything missing or invalid as zero.
+fn secs_field(value: &Value) -> Duration {
+    let secs = value.as_f64().unwrap_or_default();
+    Duration::try_from_secs_f64(secs).unwrap_or_default()
+}
+
+fn list_formats() -> Value {
+    let formats: Vec<Value> = SUPPORTED_FORMATS
+        .iter()
+        .map(|(extension, codec)| json!({"extension": extension, "codec": codec}))
+        .collect();
+    json!({
+        "status": true,
+        "message": format!("{} supported file extension(s)", formats.len()),
+        "formats": formats
+    })
+}
+
+fn print_formats(res: Value, json: bool) -> bool {
+    let formats = match res["formats"].as_array() {
+        Some(formats) if res["status"] == true && !json => formats.clone(),
+        _ => return colored_print(res, json),
+    };
+
+    println!("{}", res["message"].as_str().unwrap_or_default().blue());
+    for format in &formats {
+        let extension = format["extension"].as_str().unwrap_or_default();
+        let codec = format["codec"].as_str().unwrap_or_default();
+        println!("  .{:<6}{}", extension, codec);
+    }
+    true
+}
+
+/// Seconds since the Unix epoch, as timestamps are given to clients.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// "hh:mm:ss", for spans long enough that minutes alone get hard to read.
+fn format_hms(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// "FLAC, 44100 Hz, 2 ch" for the playing track from a `status` response, noting when the
+/// output device runs at a different rate. `None` when nothing is playing.
+fn describe_format(status: &Value) -> Option<String> {
+    let rate = status["sample_rate"].as_u64()?;
+    let mut parts = Vec::new();
+    if let Some(codec) = status["codec"].as_str() {
+        parts.push(codec.to_string());
+    }
+    parts.push(format!("{} Hz", rate));
+    if let Some(channels) = status["channels"].as_u64() {
+        parts.push(format!("{} ch", channels));
     }
+    if let Some(output) = status["output_sample_rate"].as_u64()
+        && output != rate
+    {
+        parts.push(format!("resampled to {} Hz", output));
+    }
+    Some(parts.join(", "))
+}
+
+/// "7/23" for the current track's place in the queue, from a `current` or `status` response.
+fn queue_position(response: &Value) -> Option<String> {
+    let index = response["current_index"].as_u64()?;
+    let total = response["queue_total"].as_u64()?;
+    Some(format!("{}/{}", index, total))
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,322 @@
+use crate::response::PlayerResult;
+use crate::{Actions, MAX_SPEED, MIN_SPEED, Player, RepeatMode, TrackInfo, pause_or_resume};
+use mpris_server::zbus::{self, fdo};
+use mpris_server::{
+    LoopStatus, Metadata, PlaybackRate, PlaybackStatus, PlayerInterface, Property, RootInterface,
+    Server, Time, TrackId, Volume,
+};
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast};
+
+/// Registered as `org.mpris.MediaPlayer2.udsaudio`.
+const BUS_NAME: &str = "udsaudio";
+
+/// The daemon as desktop media keys and applets see it.
+pub struct Mpris {
+    player: Arc<Mutex<Player>>,
+}
+
+/// Registers the player on the session bus and keeps the desktop up to date until the
+/// daemon exits. Without a session bus (on a headless box, say) this only logs a warning.
+pub async fn serve(player: Arc<Mutex<Player>>) {
+    let mut events = player.lock().await.subscribe();
+    let mpris = Mpris {
+        player: Arc::clone(&player),
+    };
+    let server = match Server::new(BUS_NAME, mpris).await {
+        Ok(server) => server,
+        Err(e) => {
+            tracing::warn!("Could not register on D-Bus for MPRIS: {}", e);
+            return;
+        }
+    };
+    tracing::info!("MPRIS interface registered as {}", server.bus_name());
+
+    // Every player event may have changed what the desktop shows, so resend it all.
+    loop {
+        match events.recv().await {
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+        let properties = properties(&*player.lock().await);
+        if let Err(e) = server.properties_changed(properties).await {
+            tracing::debug!("Failed to announce MPRIS changes: {}", e);
+        }
+    }
+}
+
+fn properties(player: &Player) -> Vec<Property> {
+    vec![
+        Property::PlaybackStatus(playback_status(player)),
+        Property::LoopStatus(loop_status(player.repeat)),
+        Property::Rate(player.speed as f64),
+        Property::Metadata(metadata(player.queue.first())),
+        Property::Volume(volume(player)),
+        Property::CanGoNext(!player.queue.is_empty()),
+        Property::CanGoPrevious(!player.history.is_empty()),
+        Property::CanPlay(!player.queue.is_empty()),
+        Property::CanPause(!player.queue.is_empty()),
+    ]
+}
+
+fn playback_status(player: &Player) -> PlaybackStatus {
+    match player.state() {
+        "playing" => PlaybackStatus::Playing,
+        "paused" => PlaybackStatus::Paused,
+        _ => PlaybackStatus::Stopped,
+    }
+}
+
+fn loop_status(repeat: RepeatMode) -> LoopStatus {
+    match repeat {
+        RepeatMode::Off => LoopStatus::None,
+        RepeatMode::One => LoopStatus::Track,
+        RepeatMode::All => LoopStatus::Playlist,
+    }
+}
+
+fn volume(player: &Player) -> Volume {
+    match player.muted {
+        true => 0.0,
+        false => player.volume as f64,
+    }
+}
+
+fn metadata(track: Option<&TrackInfo>) -> Metadata {
+    let mut metadata = Metadata::new();
+    let Some(track) = track else {
+        metadata.set_trackid(Some(TrackId::NO_TRACK));
+        return metadata;
+    };
+    metadata
+        .set_trackid(TrackId::try_from(format!("/org/udsaudio/track/{}", track.id).as_str()).ok());
+    metadata.set_title(Some(
+        track.title.clone().unwrap_or_else(|| track.name.clone()),
+    ));
+    metadata.set_artist(track.artist.clone().map(|artist| [artist]));
+    metadata.set_album(track.album.clone());
+    metadata.set_length(
+        track
+            .duration
+            .map(|duration| Time::from_micros(duration.as_micros() as i64)),
+    );
+    metadata.set_url(Some(url(&track.path)));
+    metadata
+}
+
+fn url(path: &str) -> String {
+    if crate::stream::is_url(path) {
+        return path.to_string();
+    }
+    match std::fs::canonicalize(path) {
+        Ok(path) => format!("file://{}", path.display()),
+        Err(_) => format!("file://{}", path),
+    }
+}
+
+/// Lets subscribers (and so the desktop) know about the change, and turns a failed command
+/// into a D-Bus error carrying its message.
+fn finish(player: &mut Player, response: PlayerResult) -> fdo::Result<()> {
+    player.publish_changes();
+    match response {
+        Ok(_) => Ok(()),
+        Err(e) => Err(fdo::Error::Failed(e.to_string())),
+    }
+}
+
+impl RootInterface for Mpris {
+    async fn raise(&self) -> fdo::Result<()> {
+        Ok(())
+    }
+
+    // Only the local socket can stop the daemon.
+    async fn quit(&self) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Use the shutdown command to stop the daemon".to_string(),
+        ))
+    }
+
+    async fn can_quit(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn fullscreen(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn set_fullscreen(&self, _fullscreen: bool) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    async fn can_set_fullscreen(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn can_raise(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn has_track_list(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn identity(&self) -> fdo::Result<String> {
+        Ok("uds-audio-player".to_string())
+    }
+
+    async fn desktop_entry(&self) -> fdo::Result<String> {
+        Ok(String::new())
+    }
+
+    async fn supported_uri_schemes(&self) -> fdo::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    async fn supported_mime_types(&self) -> fdo::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+impl PlayerInterface for Mpris {
+    async fn next(&self) -> fdo::Result<()> {
+        let mut player = self.player.lock().await;
+        let response = player.skip(1);
+        finish(&mut player, response)
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        let mut player = self.player.lock().await;
+        let response = player.prev();
+        finish(&mut player, response)
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        let response = pause_or_resume(&self.player, Actions::Pause).await;
+        finish(&mut *self.player.lock().await, response)
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        let response = pause_or_resume(&self.player, Actions::Toggle).await;
+        finish(&mut *self.player.lock().await, response)
+    }
+
+    async fn stop(&self) -> fdo::Result<()> {
+        let mut player = self.player.lock().await;
+        let response = player.stop();
+        finish(&mut player, response)
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        let response = pause_or_resume(&self.player, Actions::Resume).await;
+        finish(&mut *self.player.lock().await, response)
+    }
+
+    async fn seek(&self, _offset: Time) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Seeking isn't supported".to_string(),
+        ))
+    }
+
+    async fn set_position(&self, _track_id: TrackId, _position: Time) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Seeking isn't supported".to_string(),
+        ))
+    }
+
+    async fn open_uri(&self, _uri: String) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Use the play command to open tracks".to_string(),
+        ))
+    }
+
+    async fn playback_status(&self) -> fdo::Result<PlaybackStatus> {
+        Ok(playback_status(&*self.player.lock().await))
+    }
+
+    async fn loop_status(&self) -> fdo::Result<LoopStatus> {
+        Ok(loop_status(self.player.lock().await.repeat))
+    }
+
+    async fn set_loop_status(&self, loop_status: LoopStatus) -> zbus::Result<()> {
+        let mode = match loop_status {
+            LoopStatus::None => "off",
+            LoopStatus::Track => "one",
+            LoopStatus::Playlist => "all",
+        };
+        let mut player = self.player.lock().await;
+        let response = player.set_repeat(mode);
+        finish(&mut player, response).map_err(zbus::Error::from)
+    }
+
+    async fn rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(self.player.lock().await.speed as f64)
+    }
+
+    async fn set_rate(&self, rate: PlaybackRate) -> zbus::Result<()> {
+        let mut player = self.player.lock().await;
+        let preserve_pitch = player.preserve_pitch;
+        let response = player.set_speed(rate as f32, preserve_pitch);
+        finish(&mut player, response).map_err(zbus::Error::from)
+    }
+
+    // Shuffling reorders the queue once rather than being a mode, so there's nothing to
+    // switch on here.
+    async fn shuffle(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn set_shuffle(&self, _shuffle: bool) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    async fn metadata(&self) -> fdo::Result<Metadata> {
+        Ok(metadata(self.player.lock().await.queue.first()))
+    }
+
+    async fn volume(&self) -> fdo::Result<Volume> {
+        Ok(volume(&*self.player.lock().await))
+    }
+
+    async fn set_volume(&self, volume: Volume) -> zbus::Result<()> {
+        let mut player = self.player.lock().await;
+        let response = player.set_volume(volume as f32);
+        finish(&mut player, response).map_err(zbus::Error::from)
+    }
+
+    async fn position(&self) -> fdo::Result<Time> {
+        let position = self.player.lock().await.position();
+        Ok(Time::from_micros(position.as_micros() as i64))
+    }
+
+    async fn minimum_rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(MIN_SPEED as f64)
+    }
+
+    async fn maximum_rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(MAX_SPEED as f64)
+    }
+
+    async fn can_go_next(&self) -> fdo::Result<bool> {
+        Ok(!self.player.lock().await.queue.is_empty())
+    }
+
+    async fn can_go_previous(&self) -> fdo::Result<bool> {
+        Ok(!self.player.lock().await.history.is_empty())
+    }
+
+    async fn can_play(&self) -> fdo::Result<bool> {
+        Ok(!self.player.lock().await.queue.is_empty())
+    }
+
+    async fn can_pause(&self) -> fdo::Result<bool> {
+        Ok(!self.player.lock().await.queue.is_empty())
+    }
+
+    async fn can_seek(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn can_control(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+}
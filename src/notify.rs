@@ -0,0 +1,57 @@
+use crate::TrackInfo;
+use notify_rust::Notification;
+use std::path::{Path, PathBuf};
+
+const APP_NAME: &str = "udsaudio";
+
+/// Shows a "now playing" popup for `track`.
+///
+/// Talking to the notification daemon blocks, so this runs on the blocking pool. Failures,
+/// such as there being no notification daemon on a headless box, are only logged.
+pub fn track_started(track: &TrackInfo) {
+    let track = track.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut notification = Notification::new();
+        notification.appname(APP_NAME).summary(&summary(&track));
+        if let Some(body) = body(&track) {
+            notification.body(&body);
+        }
+        if let Some(cover) = write_cover(Path::new(&track.path)) {
+            notification.image_path(&cover.display().to_string());
+        }
+        if let Err(e) = notification.show() {
+            tracing::warn!("Could not show notification: {}", e);
+        }
+    });
+}
+
+fn summary(track: &TrackInfo) -> String {
+    match &track.title {
+        Some(title) => title.clone(),
+        None => track.name.clone(),
+    }
+}
+
+/// "Artist – Album", or whichever of the two is known.
+fn body(track: &TrackInfo) -> Option<String> {
+    match (&track.artist, &track.album) {
+        (Some(artist), Some(album)) => Some(format!("{} – {}", artist, album)),
+        (Some(artist), None) => Some(artist.clone()),
+        (None, Some(album)) => Some(album.clone()),
+        (None, None) => None,
+    }
+}
+
+/// Notification servers want a file path for images, so the embedded cover is written to
+/// a temporary file that each new track overwrites.
+fn write_cover(path: &Path) -> Option<PathBuf> {
+    let (data, ext) = crate::tags::read_cover(path)?;
+    let cover = std::env::temp_dir().join(format!("{}-cover.{}", APP_NAME, ext));
+    match std::fs::write(&cover, data) {
+        Ok(()) => Some(cover),
+        Err(e) => {
+            tracing::warn!("Could not write cover art: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "pls"];
+
+/// A single track listed in a playlist file.
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| PLAYLIST_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Reads a playlist in whichever format its extension says.
+pub fn parse(path: &Path) -> std::io::Result<Vec<PlaylistEntry>> {
+    let is_pls = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pls"));
+    match is_pls {
+        true => parse_pls(path),
+        false => parse_m3u(path),
+    }
+}
+
+/// Reads an `.m3u`/`.m3u8` playlist.
+///
+/// Relative entries are resolved against the playlist's directory; URLs are kept as they
+/// are. `#EXTINF` lines supply the title and duration of the entry that follows them; every
+/// other `#` line is ignored.
+pub fn parse_m3u(path: &Path) -> std::io::Result<Vec<PlaylistEntry>> {
+    let contents = std::fs::read(path)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut entries = Vec::new();
+    let mut info = None;
+    for line in contents.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Some(parse_extinf(extinf));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (duration, title) = info.take().unwrap_or_default();
+        // Stream URLs would otherwise be mangled into a path under the playlist's directory.
+        let path = if crate::stream::is_url(line) {
+            PathBuf::from(line)
+        } else {
+            base.join(line)
+        };
+        entries.push(PlaylistEntry {
+            path,
+            title,
+            duration,
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads a `.pls` playlist, as handed out by internet radio directories.
+///
+/// Entries are the numbered `FileN=` keys, taken in numeric order with their `TitleN=` and
+/// `LengthN=` (`-1` for a stream of unknown length). Relative files resolve against the
+/// playlist's directory like in `parse_m3u`. Lines that aren't a recognised `key=value`, and
+/// titles or lengths without a file, are skipped rather than failing the whole playlist.
+fn parse_pls(path: &Path) -> std::io::Result<Vec<PlaylistEntry>> {
+    let contents = std::fs::read(path)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut files: BTreeMap<u32, String> = BTreeMap::new();
+    let mut titles: BTreeMap<u32, String> = BTreeMap::new();
+    let mut lengths: BTreeMap<u32, Duration> = BTreeMap::new();
+    for line in contents.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let numbered = |prefix: &str| key.strip_prefix(prefix)?.parse::<u32>().ok();
+        if value.is_empty() {
+            continue;
+        }
+        if let Some(n) = numbered("file") {
+            files.insert(n, value.to_string());
+        } else if let Some(n) = numbered("title") {
+            titles.insert(n, value.to_string());
+        } else if let Some(n) = numbered("length")
+            && let Some(length) = value
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        {
+            lengths.insert(n, length);
+        }
+    }
+
+    let entries = files
+        .into_iter()
+        .map(|(n, file)| PlaylistEntry {
+            path: if crate::stream::is_url(&file) {
+                PathBuf::from(file)
+            } else {
+                base.join(file)
+            },
+            title: titles.remove(&n),
+            duration: lengths.remove(&n),
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Parses the `<seconds>[ attributes],<title>` part of an `#EXTINF` line. A negative length
+/// means the duration is unknown.
+fn parse_extinf(extinf: &str) -> (Option<Duration>, Option<String>) {
+    let (length, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+    let duration = length
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+    let title = Some(title.trim().to_string()).filter(|title| !title.is_empty());
+    (duration, title)
+}
+
+/// Writes `entries` as an extended `.m3u` playlist that `parse_m3u` reads back unchanged.
+/// Paths are written as given, so absolute paths keep the file valid wherever it is moved.
+pub fn write_m3u(path: &Path, entries: &[PlaylistEntry]) -> std::io::Result<()> {
+    let mut contents = String::from("#EXTM3U\n");
+    for entry in entries {
+        if entry.title.is_some() || entry.duration.is_some() {
+            let secs = entry
+                .duration
+                .map_or(-1, |duration| duration.as_secs() as i64);
+            contents.push_str(&format!(
+                "#EXTINF:{},{}\n",
+                secs,
+                entry.title.as_deref().unwrap_or("")
+            ));
+        }
+        contents.push_str(&format!("{}\n", entry.path.display()));
+    }
+    std::fs::write(path, contents)
+}
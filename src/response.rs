@@ -0,0 +1,130 @@
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::fmt;
+
+/// What a command answers with when it works: a message for people, plus whatever fields
+/// scripts read.
+pub struct PlayerResponse {
+    pub message: String,
+    fields: Map<String, Value>,
+}
+
+impl PlayerResponse {
+    pub fn new(message: impl Into<String>) -> Self {
+        PlayerResponse {
+            message: message.into(),
+            fields: Map::new(),
+        }
+    }
+
+    /// Adds a machine-readable field alongside the message.
+    pub fn with(mut self, key: &str, value: impl Serialize) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, value: impl Serialize) {
+        self.fields.insert(key.to_string(), to_value(value));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.get(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.fields.remove(key)
+    }
+}
+
+/// Why a command failed. The variant goes out as `code`, so scripts can tell failures apart
+/// without matching on the wording of `message`.
+pub enum PlayerError {
+    /// Nothing is loaded, or nothing is playing, for the command to act on.
+    Idle(String),
+    /// An index, bookmark, or saved position that doesn't exist.
+    NotFound(String),
+    /// A missing field, or one the command can't use.
+    InvalidArgument(String),
+    /// The queue is already at `--max-queue`.
+    QueueFull(String),
+    /// A track or playlist couldn't be opened, decoded, or sought in.
+    Playback(String),
+    /// The request isn't allowed from here, or the connection handles it instead.
+    Refused(String),
+    /// A file the daemon keeps, such as the history log, couldn't be written.
+    Storage(String),
+    /// Any of the above with extra fields for scripts, such as the files that failed to load.
+    Detailed(Box<PlayerError>, Map<String, Value>),
+}
+
+impl PlayerError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PlayerError::Idle(_) => "idle",
+            PlayerError::NotFound(_) => "not_found",
+            PlayerError::InvalidArgument(_) => "invalid_argument",
+            PlayerError::QueueFull(_) => "queue_full",
+            PlayerError::Playback(_) => "playback",
+            PlayerError::Refused(_) => "refused",
+            PlayerError::Storage(_) => "storage",
+            PlayerError::Detailed(error, _) => error.code(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            PlayerError::Idle(message)
+            | PlayerError::NotFound(message)
+            | PlayerError::InvalidArgument(message)
+            | PlayerError::QueueFull(message)
+            | PlayerError::Playback(message)
+            | PlayerError::Refused(message)
+            | PlayerError::Storage(message) => message,
+            PlayerError::Detailed(error, _) => error.message(),
+        }
+    }
+
+    /// Adds a machine-readable field alongside the message.
+    pub fn with(self, key: &str, value: impl Serialize) -> Self {
+        let (error, mut fields) = match self {
+            PlayerError::Detailed(error, fields) => (error, fields),
+            error => (Box::new(error), Map::new()),
+        };
+        fields.insert(key.to_string(), to_value(value));
+        PlayerError::Detailed(error, fields)
+    }
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+pub type PlayerResult = Result<PlayerResponse, PlayerError>;
+
+/// The wire form of a command's outcome: always `status` and `message`, a `code` when it
+/// failed, and any extra fields next to them.
+pub fn to_json(result: &PlayerResult) -> Value {
+    let (mut response, fields) = match result {
+        Ok(response) => (
+            json!({"status": true, "message": response.message}),
+            Some(&response.fields),
+        ),
+        Err(error) => (
+            json!({"status": false, "message": error.message(), "code": error.code()}),
+            match error {
+                PlayerError::Detailed(_, fields) => Some(fields),
+                _ => None,
+            },
+        ),
+    };
+    for (key, value) in fields.into_iter().flatten() {
+        response[key] = value.clone();
+    }
+    response
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
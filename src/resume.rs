@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Tracks shorter than this always start from the beginning.
+pub const MIN_LENGTH: Duration = Duration::from_secs(5 * 60);
+
+/// Positions this close to either end aren't worth resuming from.
+const MARGIN: Duration = Duration::from_secs(10);
+
+/// Where each long track was last left off, kept on disk so it survives restarts.
+pub struct Positions {
+    file: Option<PathBuf>,
+    positions: HashMap<String, f64>,
+}
+
+impl Positions {
+    /// Reads the saved positions from `file`. A missing or unreadable file starts empty.
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let positions = file
+            .as_ref()
+            .and_then(|file| std::fs::read(file).ok())
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+        Positions { file, positions }
+    }
+
+    pub fn get(&self, path: &str) -> Option<Duration> {
+        let secs = *self.positions.get(&key(path))?;
+        Duration::try_from_secs_f64(secs).ok()
+    }
+
+    /// Records where `path` was left. Short tracks are ignored, and a position near either
+    /// end forgets the track instead, since there'd be nothing to resume.
+    pub fn set(&mut self, path: &str, position: Duration, length: Option<Duration>) {
+        let Some(length) = length.filter(|length| *length >= MIN_LENGTH) else {
+            return;
+        };
+        if position < MARGIN || position + MARGIN > length {
+            self.forget(path);
+            return;
+        }
+        self.positions.insert(key(path), position.as_secs_f64());
+        self.save();
+    }
+
+    /// Drops the saved position for `path`. Returns whether there was one.
+    pub fn forget(&mut self, path: &str) -> bool {
+        let removed = self.positions.remove(&key(path)).is_some();
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let saved = file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(file, serde_json::to_vec(&self.positions)?));
+        if let Err(e) = saved {
+            tracing::error!("Failed to save positions to {}: {}", file.display(), e);
+        }
+    }
+}
+
+/// The same file reached through different relative paths or symlinks shares one entry.
+pub fn key(path: &str) -> String {
+    std::fs::canonicalize(Path::new(path))
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
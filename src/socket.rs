@@ -0,0 +1,151 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixListener, UnixStream};
+
+/// How long whatever holds a socket file has to answer a `ping` before it's taken to be stale.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A connection to the daemon, whichever transport it went over.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Where the daemon listens and the CLI connects.
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+    /// A socket file on disk.
+    Path(PathBuf),
+    /// A Linux abstract-namespace socket. It has no file, so nothing is left behind when the
+    /// daemon exits.
+    Abstract(String),
+    /// A daemon started with `--listen`, reached over TCP. Only ever connected to, since
+    /// the daemon always binds its local socket as well.
+    Tcp(String),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Path(path) => write!(f, "{}", path.display()),
+            Endpoint::Abstract(name) => write!(f, "@{}", name),
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Starts listening, refusing if another daemon already is.
+    ///
+    /// A socket file left behind by a daemon that crashed is removed first, but only once it
+    /// has failed to answer a `ping`: anything that still answers is a live daemon, and
+    /// taking its path would leave it unreachable.
+    pub async fn bind(&self) -> io::Result<UnixListener> {
+        match self {
+            Endpoint::Path(path) => {
+                if path.exists() {
+                    let stale = match UnixStream::connect(path).await {
+                        Ok(stream) => match answers_ping(stream).await {
+                            true => return Err(already_running()),
+                            false => true,
+                        },
+                        Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+                    };
+                    if stale {
+                        tracing::info!("Removing stale socket {}", path.display());
+                        let _ = std::fs::remove_file(path);
+                    }
+                }
+                UnixListener::bind(path)
+            }
+            Endpoint::Abstract(name) => bind_abstract(name).map_err(|e| match e.kind() {
+                io::ErrorKind::AddrInUse => already_running(),
+                _ => e,
+            }),
+            Endpoint::Tcp(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the daemon binds TCP addresses with --listen",
+            )),
+        }
+    }
+
+    pub async fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(match self {
+            Endpoint::Path(path) => Box::new(UnixStream::connect(path).await?),
+            Endpoint::Abstract(name) => Box::new(connect_abstract(name)?),
+            Endpoint::Tcp(addr) => Box::new(TcpStream::connect(addr).await?),
+        })
+    }
+
+    /// Removes the socket file, if there is one.
+    pub fn cleanup(&self) {
+        if let Endpoint::Path(path) = self
+            && let Err(e) = std::fs::remove_file(path)
+        {
+            tracing::error!("Failed to remove socket {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Whether whatever accepted `stream` replies to a `ping` the way a daemon does. A daemon
+/// that wants a token still answers, with a refusal.
+async fn answers_ping(stream: UnixStream) -> bool {
+    let probe = async {
+        let mut stream = BufReader::new(stream);
+        stream.write_all(b"{\"action\":\"ping\"}\n").await?;
+        let mut reply = String::new();
+        stream.read_line(&mut reply).await?;
+        Ok::<_, io::Error>(reply)
+    };
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(reply)) => serde_json::from_str::<serde_json::Value>(&reply)
+            .is_ok_and(|reply| reply["status"].is_boolean()),
+        _ => false,
+    }
+}
+
+fn already_running() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AddrInUse,
+        "another daemon is already running there; stop it with `shutdown` first",
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+// Connecting to a local socket doesn't wait on anything, so the blocking call is fine here.
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &str) -> io::Result<UnixListener> {
+    Err(abstract_unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_name: &str) -> io::Result<UnixStream> {
+    Err(abstract_unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    )
+}
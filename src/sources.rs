@@ -0,0 +1,1775 @@
+use rodio::Source;
+use rodio::source::{SeekError, Zero};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Wraps a source so it can be dropped from the sink after it has been appended.
+///
+/// rodio's `Sink` has no way to remove a queued source, so every track is appended through
+/// this adapter. Setting the shared flag makes the source end immediately, and the sink
+/// moves straight on to whatever follows it.
+pub struct Cancellable<S> {
+    inner: S,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<S> Cancellable<S> {
+    pub fn new(inner: S, cancelled: Arc<AtomicBool>) -> Self {
+        Cancellable { inner, cancelled }
+    }
+}
+
+impl<S> Iterator for Cancellable<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.inner.next()
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Cancellable<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+/// How many samples each step of a prefetch (or a read past its buffer) decodes at a time.
+const PREFETCH_BATCH: usize = 4096;
+
+/// Decodes the start of a track ahead of time, off the audio thread.
+///
+/// Reading the first packets of a file can stall on slow storage, and when that happens on
+/// the audio thread it's heard as a hitch between tracks. The player hands the `Prefetcher`
+/// side to a blocking task shortly before the track is due, which fills a buffer that
+/// playback drains before reading the decoder itself. Until then nothing is decoded early.
+///
+/// The format is taken from the decoder when the track is opened, so like `Crossfade` this
+/// assumes it doesn't change partway through.
+pub struct Prefetch<S> {
+    shared: Arc<Mutex<Ahead<S>>>,
+    // Samples taken from the shared buffer in one go, so the lock isn't touched per sample.
+    local: VecDeque<f32>,
+    channels: rodio::ChannelCount,
+    sample_rate: rodio::SampleRate,
+    total_duration: Option<Duration>,
+}
+
+struct Ahead<S> {
+    inner: S,
+    samples: VecDeque<f32>,
+    exhausted: bool,
+}
+
+impl<S> Ahead<S>
+where
+    S: Source,
+{
+    /// Decodes up to `count` more samples onto the buffer.
+    fn decode(&mut self, count: usize) {
+        for _ in 0..count {
+            match self.inner.next() {
+                Some(sample) => self.samples.push_back(sample),
+                None => {
+                    self.exhausted = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The player's side of a `Prefetch`, kept without knowing the source's type.
+pub trait Prefetcher: Send + Sync {
+    /// Decodes until `window` of audio is buffered, the track ends, or `cancelled` is set.
+    /// Blocks, so it belongs on a blocking task.
+    fn fill(&self, window: Duration, cancelled: &AtomicBool);
+
+    /// Drops whatever has been buffered, for a track that will never play. Doesn't wait for
+    /// a fill in progress, which notices the cancellation and does the same itself.
+    fn release(&self);
+}
+
+impl<S> Prefetch<S>
+where
+    S: Source + Send + 'static,
+{
+    pub fn new(inner: S) -> (Self, Arc<dyn Prefetcher>) {
+        let channels = inner.channels();
+        let sample_rate = inner.sample_rate();
+        let total_duration = inner.total_duration();
+        let shared = Arc::new(Mutex::new(Ahead {
+            inner,
+            samples: VecDeque::new(),
+            exhausted: false,
+        }));
+        let prefetch = Prefetch {
+            shared: Arc::clone(&shared),
+            local: VecDeque::new(),
+            channels,
+            sample_rate,
+            total_duration,
+        };
+        (prefetch, shared)
+    }
+}
+
+impl<S> Prefetcher for Mutex<Ahead<S>>
+where
+    S: Source + Send,
+{
+    fn fill(&self, window: Duration, cancelled: &AtomicBool) {
+        loop {
+            // Decoding a batch at a time lets playback get at what's ready if the track
+            // starts before the buffer is full.
+            let mut ahead = self.lock().unwrap();
+            if cancelled.load(Ordering::Relaxed) {
+                ahead.samples = VecDeque::new();
+                return;
+            }
+            let frames = ahead.inner.sample_rate() as f64 * window.as_secs_f64();
+            let limit = frames as usize * ahead.inner.channels() as usize;
+            if ahead.exhausted || ahead.samples.len() >= limit {
+                return;
+            }
+            let count = PREFETCH_BATCH.min(limit - ahead.samples.len());
+            ahead.decode(count);
+        }
+    }
+
+    fn release(&self) {
+        if let Ok(mut ahead) = self.try_lock() {
+            ahead.samples = VecDeque::new();
+        }
+    }
+}
+
+impl<S> Iterator for Prefetch<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if self.local.is_empty() {
+            let mut ahead = self.shared.lock().unwrap();
+            if ahead.samples.is_empty() && !ahead.exhausted {
+                ahead.decode(PREFETCH_BATCH);
+            }
+            std::mem::swap(&mut self.local, &mut ahead.samples);
+        }
+        self.local.pop_front()
+    }
+}
+
+impl<S> Source for Prefetch<S>
+where
+    S: Source,
+{
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> rodio::ChannelCount {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let mut ahead = self.shared.lock().unwrap();
+        ahead.inner.try_seek(pos)?;
+        ahead.samples.clear();
+        ahead.exhausted = false;
+        self.local.clear();
+        Ok(())
+    }
+}
+
+/// How much silence a `TrimSilence` has cut from its track so far, for the player to report.
+#[derive(Default)]
+pub struct Trimmed {
+    start_ms: AtomicU64,
+    end_ms: AtomicU64,
+}
+
+impl Trimmed {
+    pub fn start(&self) -> Duration {
+        Duration::from_millis(self.start_ms.load(Ordering::Relaxed))
+    }
+
+    /// Only known once the track has been read to the end.
+    pub fn end(&self) -> Duration {
+        Duration::from_millis(self.end_ms.load(Ordering::Relaxed))
+    }
+}
+
+/// Skips silence at the start of a track and stops before the silence at its end.
+///
+/// A frame is silent when every channel is below `threshold`, and at most `max` is cut from
+/// either end, so a quiet intro or fade-out isn't lost. Silence partway through a track has
+/// to be told apart from silence at the end, so playback runs `max` behind what has been
+/// read: once the source runs out, whatever is left in the window after the last audible
+/// frame is dropped. The format is taken when the source is wrapped, like `Balance`.
+pub struct TrimSilence<S> {
+    inner: S,
+    channels: usize,
+    sample_rate: rodio::SampleRate,
+    threshold: f32,
+    // The most samples either end may lose.
+    max: usize,
+    window: VecDeque<f32>,
+    // Samples at the front of the window up to and including the last audible frame.
+    audible: usize,
+    // How far into the current frame playback is; the window is only topped up, and the
+    // end only trimmed, between frames.
+    offset: usize,
+    started: bool,
+    exhausted: bool,
+    trimmed: Arc<Trimmed>,
+}
+
+impl<S> TrimSilence<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, threshold: f32, max: Duration, trimmed: Arc<Trimmed>) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let sample_rate = inner.sample_rate();
+        let max = (sample_rate as f64 * max.as_secs_f64()) as usize * channels;
+        TrimSilence {
+            inner,
+            channels,
+            sample_rate,
+            threshold,
+            max,
+            window: VecDeque::with_capacity(max + channels),
+            audible: 0,
+            offset: 0,
+            started: false,
+            exhausted: false,
+            trimmed,
+        }
+    }
+
+    /// Reads one frame onto the window. `None` once the source has run out.
+    fn read_frame(&mut self) -> Option<()> {
+        let mut loud = false;
+        for channel in 0..self.channels {
+            match self.inner.next() {
+                Some(sample) => {
+                    loud |= sample.abs() >= self.threshold;
+                    self.window.push_back(sample);
+                }
+                // A frame cut short is kept as it is.
+                None if channel > 0 => break,
+                None => return None,
+            }
+        }
+        if loud {
+            self.audible = self.window.len();
+        }
+        Some(())
+    }
+
+    fn skip_leading(&mut self) {
+        self.started = true;
+        let mut skipped = 0;
+        while skipped < self.max {
+            if self.read_frame().is_none() {
+                self.exhausted = true;
+                break;
+            }
+            if self.audible > 0 {
+                break;
+            }
+            skipped += self.window.len();
+            self.window.clear();
+        }
+        let skipped = self.millis(skipped);
+        self.trimmed.start_ms.store(skipped, Ordering::Relaxed);
+    }
+
+    /// How long `samples` samples of this source last, in milliseconds.
+    fn millis(&self, samples: usize) -> u64 {
+        let frames = (samples / self.channels) as u64;
+        frames * 1000 / self.sample_rate.max(1) as u64
+    }
+}
+
+impl<S> Iterator for TrimSilence<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if !self.started {
+            self.skip_leading();
+        }
+        if self.offset == 0 {
+            // Keep a full window read ahead past the frame about to play, so trailing silence
+            // is seen before it's played.
+            while !self.exhausted && self.window.len() < self.max + self.channels {
+                if self.read_frame().is_none() {
+                    self.exhausted = true;
+                }
+            }
+            if self.exhausted && self.audible == 0 {
+                if !self.window.is_empty() {
+                    let trailing = self.millis(self.window.len());
+                    self.trimmed.end_ms.store(trailing, Ordering::Relaxed);
+                    self.window.clear();
+                }
+                return None;
+            }
+        }
+        let sample = self.window.pop_front()?;
+        self.audible = self.audible.saturating_sub(1);
+        self.offset = (self.offset + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for TrimSilence<S>
+where
+    S: Source,
+{
+    // The format is fixed, and the window shifts where any span would end.
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> rodio::ChannelCount {
+        self.channels as rodio::ChannelCount
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    // A seek lands exactly where it was asked to, so nothing is skipped at the start after it.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.window.clear();
+        self.audible = 0;
+        self.offset = 0;
+        self.started = true;
+        self.exhausted = false;
+        Ok(())
+    }
+}
+
+/// Follows a source with `gap` of silence in the same format, so consecutive tracks have a
+/// fixed pause between them. The format is taken when the source is wrapped, like `Balance`.
+pub struct Gap<S> {
+    inner: S,
+    channels: rodio::ChannelCount,
+    sample_rate: rodio::SampleRate,
+    // Samples of silence to play once the source runs out, across all channels.
+    length: usize,
+    silence: Option<std::iter::Take<Zero>>,
+}
+
+impl<S> Gap<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, gap: Duration) -> Self {
+        let channels = inner.channels();
+        let sample_rate = inner.sample_rate();
+        // Whole frames, so the next track starts on its first channel.
+        let frames = (sample_rate as f64 * gap.as_secs_f64()).round() as usize;
+        Gap {
+            inner,
+            channels,
+            sample_rate,
+            length: frames * channels as usize,
+            silence: None,
+        }
+    }
+}
+
+impl<S> Iterator for Gap<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if self.silence.is_none() {
+            if let Some(sample) = self.inner.next() {
+                return Some(sample);
+            }
+            let zero = Zero::new(self.channels, self.sample_rate);
+            self.silence = Some(zero.take(self.length));
+        }
+        self.silence.as_mut()?.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.silence {
+            Some(silence) => silence.size_hint(),
+            None => {
+                let (lower, upper) = self.inner.size_hint();
+                (
+                    lower.saturating_add(self.length),
+                    upper.and_then(|upper| upper.checked_add(self.length)),
+                )
+            }
+        }
+    }
+}
+
+impl<S> Source for Gap<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        match &self.silence {
+            Some(silence) => silence.size_hint().1,
+            None => self.inner.current_span_len(),
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        match self.silence {
+            Some(_) => self.channels,
+            None => self.inner.channels(),
+        }
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        match self.silence {
+            Some(_) => self.sample_rate,
+            None => self.inner.sample_rate(),
+        }
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        let gap = Duration::from_secs_f64(
+            (self.length / self.channels.max(1) as usize) as f64 / self.sample_rate.max(1) as f64,
+        );
+        Some(self.inner.total_duration()? + gap)
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.silence = None;
+        Ok(())
+    }
+}
+
+/// Length of each overlapped grain in the time stretcher. Long enough to hold a pitch period
+/// of speech or music, short enough that transients don't smear noticeably.
+const GRAIN: Duration = Duration::from_millis(30);
+
+/// Changes the tempo of a source without changing its pitch.
+///
+/// This is WSOLA: the output is built from Hann-windowed grains that overlap by half, and the
+/// grains are read from the input further apart (faster) or closer together (slower) than they
+/// are written. Each grain is nudged within a small window to where it lines up best with the
+/// previous one, which avoids the phasing plain overlap-add produces.
+///
+/// The ratio is shared so the player can change it while the source is playing. At 1.0 the
+/// samples pass straight through until the first change.
+pub struct TimeStretch<S> {
+    inner: S,
+    ratio: Arc<AtomicU32>,
+    channels: usize,
+    hop: usize,
+    tolerance: usize,
+    window: Vec<f32>,
+    // Interleaved input frames that later grains may still read.
+    input: Vec<f32>,
+    // Where the next grain nominally starts, in frames into `input`.
+    next_start: f64,
+    // Where the previous grain would have continued, which the next one is matched against.
+    continuation: Option<usize>,
+    // Second half of the previous grain, waiting to be added to the next one.
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    stretching: bool,
+    passed: usize,
+    exhausted: bool,
+    finished: bool,
+}
+
+impl<S> TimeStretch<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, ratio: Arc<AtomicU32>) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let hop = ((inner.sample_rate() as f64 * GRAIN.as_secs_f64()) as usize / 2).max(1);
+        let window = (0..hop * 2)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / (hop * 2) as f32).cos())
+            .collect();
+        TimeStretch {
+            inner,
+            ratio,
+            channels,
+            hop,
+            tolerance: hop / 2,
+            window,
+            input: Vec::new(),
+            next_start: 0.0,
+            continuation: None,
+            overlap: vec![0.0; hop * channels],
+            output: VecDeque::new(),
+            stretching: false,
+            passed: 0,
+            exhausted: false,
+            finished: false,
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        f32::from_bits(self.ratio.load(Ordering::Relaxed)) as f64
+    }
+
+    fn frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    /// Input sample at `frame`, or silence past the end of the source.
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        self.input
+            .get(frame * self.channels + channel)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Reads from the inner source until `frames` input frames are buffered.
+    fn fill(&mut self, frames: usize) {
+        while !self.exhausted && self.frames() < frames {
+            match self.inner.next() {
+                Some(sample) => self.input.push(sample),
+                None => self.exhausted = true,
+            }
+        }
+    }
+
+    /// Picks the start within the tolerance of `nominal` whose opening best matches the
+    /// natural continuation of the previous grain.
+    fn best_start(&self, nominal: usize) -> usize {
+        let Some(continuation) = self.continuation else {
+            return nominal;
+        };
+        let lowest = nominal.saturating_sub(self.tolerance);
+        let highest = nominal + self.tolerance;
+        let mut best = (f32::MIN, nominal);
+        for start in lowest..=highest {
+            // Every fourth frame is plenty to find the alignment.
+            let score: f32 = (0..self.hop)
+                .step_by(4)
+                .map(|k| {
+                    (0..self.channels)
+                        .map(|c| self.sample(continuation + k, c) * self.sample(start + k, c))
+                        .sum::<f32>()
+                })
+                .sum();
+            if score > best.0 {
+                best = (score, start);
+            }
+        }
+        best.1
+    }
+
+    /// Writes one hop of output.
+    fn step(&mut self) {
+        let nominal = self.next_start as usize;
+        self.fill(nominal + self.tolerance + self.hop * 2);
+        if self.exhausted && nominal >= self.frames() {
+            // Nothing left to read, so let the last grain ring out.
+            self.output.extend(self.overlap.drain(..));
+            self.finished = true;
+            return;
+        }
+
+        let start = self.best_start(nominal);
+        for k in 0..self.hop {
+            for c in 0..self.channels {
+                let i = k * self.channels + c;
+                let rising = self.window[k] * self.sample(start + k, c);
+                self.output.push_back(self.overlap[i] + rising);
+                self.overlap[i] = self.window[self.hop + k] * self.sample(start + self.hop + k, c);
+            }
+        }
+        self.next_start += self.hop as f64 * self.ratio();
+
+        // Drop input that neither the next search window nor the continuation can reach.
+        let keep_from = (self.next_start as usize)
+            .saturating_sub(self.tolerance)
+            .min(start + self.hop);
+        let drop = keep_from.min(self.frames());
+        self.input.drain(..drop * self.channels);
+        self.next_start -= drop as f64;
+        self.continuation = Some(start + self.hop - drop);
+    }
+}
+
+impl<S> Iterator for TimeStretch<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if !self.stretching {
+            // Only switch on a frame boundary so the channels stay in order.
+            if self.ratio() == 1.0 || !self.passed.is_multiple_of(self.channels) {
+                self.passed += 1;
+                return self.inner.next();
+            }
+            self.stretching = true;
+        }
+        while self.output.is_empty() {
+            if self.finished {
+                return None;
+            }
+            self.step();
+        }
+        self.output.pop_front()
+    }
+}
+
+impl<S> Source for TimeStretch<S>
+where
+    S: Source,
+{
+    fn current_span_len(&self) -> Option<usize> {
+        if self.stretching {
+            None
+        } else {
+            self.inner.current_span_len()
+        }
+    }
+
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.input.clear();
+        self.output.clear();
+        self.overlap.fill(0.0);
+        self.next_start = 0.0;
+        self.continuation = None;
+        self.passed = 0;
+        self.exhausted = false;
+        self.finished = false;
+        Ok(())
+    }
+}
+
+/// The end of a track that has been handed to the next one to fade out underneath it.
+struct Tail {
+    samples: VecDeque<f32>,
+    channels: rodio::ChannelCount,
+    sample_rate: rodio::SampleRate,
+}
+
+/// Where an ending track leaves its tail for the next track to pick up.
+///
+/// The player only marks the bus ready while the current track has a successor in the sink
+/// with the same channel count and sample rate, so a tail is never handed off with nothing
+/// to mix it into.
+#[derive(Default)]
+pub struct CrossfadeBus {
+    ready: AtomicBool,
+    tail: Mutex<Option<Tail>>,
+}
+
+impl CrossfadeBus {
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+}
+
+/// Overlaps the end of one track with the start of the next.
+///
+/// rodio's sink plays sources strictly one after another, so the overlap is done here. Each
+/// track reads `length` ahead of what it plays. When it runs out, the lookahead holds its
+/// last `length` of audio, which is faded out and left on the bus instead of being played,
+/// and the track ends early. The next track takes the tail as it starts and mixes it in
+/// under its own fade in.
+pub struct Crossfade<S> {
+    inner: S,
+    bus: Arc<CrossfadeBus>,
+    length: usize,
+    ahead: VecDeque<f32>,
+    incoming: Option<VecDeque<f32>>,
+    incoming_frames: usize,
+    emitted: usize,
+    started: bool,
+    exhausted: bool,
+}
+
+impl<S> Crossfade<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, length: Duration, bus: Arc<CrossfadeBus>) -> Self {
+        let frames = (inner.sample_rate() as f64 * length.as_secs_f64()) as usize;
+        let length = frames * inner.channels() as usize;
+        Crossfade {
+            inner,
+            bus,
+            length,
+            ahead: VecDeque::with_capacity(length),
+            incoming: None,
+            incoming_frames: 0,
+            emitted: 0,
+            started: false,
+            exhausted: false,
+        }
+    }
+
+    /// Takes the previous track's tail off the bus, if it matches this track's format.
+    fn take_tail(&mut self) {
+        let Some(tail) = self.bus.tail.lock().unwrap().take() else {
+            return;
+        };
+        if tail.channels == self.inner.channels() && tail.sample_rate == self.inner.sample_rate() {
+            self.incoming_frames = tail.samples.len() / tail.channels.max(1) as usize;
+            self.incoming = Some(tail.samples);
+        }
+    }
+
+    /// Fades out the lookahead and leaves it on the bus for the next track.
+    fn hand_off(&mut self) {
+        let channels = self.inner.channels().max(1) as usize;
+        let frames = self.ahead.len() / channels;
+        let samples = self
+            .ahead
+            .drain(..)
+            .enumerate()
+            .map(|(i, sample)| sample * (1.0 - (i / channels) as f32 / frames as f32))
+            .collect();
+        *self.bus.tail.lock().unwrap() = Some(Tail {
+            samples,
+            channels: self.inner.channels(),
+            sample_rate: self.inner.sample_rate(),
+        });
+    }
+}
+
+impl<S> Iterator for Crossfade<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if !self.started {
+            self.started = true;
+            self.take_tail();
+        }
+
+        // Build up the lookahead gradually rather than decoding it all in one callback.
+        let reads = if self.ahead.len() < self.length { 2 } else { 1 };
+        for _ in 0..reads {
+            if self.exhausted {
+                break;
+            }
+            match self.inner.next() {
+                Some(sample) => self.ahead.push_back(sample),
+                None => self.exhausted = true,
+            }
+        }
+
+        let channels = self.inner.channels().max(1) as usize;
+        if self.exhausted
+            && self.emitted.is_multiple_of(channels)
+            && !self.ahead.is_empty()
+            && self.bus.ready.load(Ordering::SeqCst)
+        {
+            self.hand_off();
+        }
+
+        let mut sample = self.ahead.pop_front()?;
+        if let Some(tail) = &mut self.incoming {
+            let frame = self.emitted / channels;
+            let gain = (frame as f32 / self.incoming_frames.max(1) as f32).min(1.0);
+            sample = sample * gain + tail.pop_front().unwrap_or(0.0);
+            if tail.is_empty() {
+                self.incoming = None;
+            }
+        }
+        self.emitted += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Crossfade<S>
+where
+    S: Source,
+{
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.ahead.clear();
+        self.incoming = None;
+        self.exhausted = false;
+        Ok(())
+    }
+}
+
+/// How often a meter publishes new levels. Fast enough to drive a level bar smoothly.
+const METER_WINDOW: Duration = Duration::from_millis(50);
+
+/// Levels from the most recent metering window, shared between the playing sources and the
+/// daemon.
+#[derive(Default)]
+pub struct LevelMeter {
+    levels: Mutex<Levels>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Levels {
+    /// Peak absolute sample per channel, 0.0 to 1.0.
+    pub peak: Vec<f32>,
+    /// Root mean square per channel, 0.0 to 1.0.
+    pub rms: Vec<f32>,
+    pub updated: Option<std::time::Instant>,
+}
+
+impl LevelMeter {
+    pub fn levels(&self) -> Levels {
+        self.levels.lock().unwrap().clone()
+    }
+}
+
+/// Measures the peak and RMS of each channel of a source as it plays, publishing them to a
+/// shared `LevelMeter` once per window.
+///
+/// Samples are accumulated locally and the shared state is only touched at the end of a
+/// window, with `try_lock` so a reader can never stall playback.
+pub struct Meter<S> {
+    inner: S,
+    meter: Arc<LevelMeter>,
+    peak: Vec<f32>,
+    sum_squares: Vec<f32>,
+    // Samples seen in the current window, across all channels.
+    counted: usize,
+    channel: usize,
+}
+
+impl<S> Meter<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, meter: Arc<LevelMeter>) -> Self {
+        let channels = inner.channels() as usize;
+        Meter {
+            inner,
+            meter,
+            peak: vec![0.0; channels],
+            sum_squares: vec![0.0; channels],
+            counted: 0,
+            channel: 0,
+        }
+    }
+
+    fn publish(&mut self) {
+        let frames = (self.counted / self.peak.len()).max(1) as f32;
+        if let Ok(mut levels) = self.meter.levels.try_lock() {
+            levels.peak = self.peak.iter().map(|peak| peak.min(1.0)).collect();
+            levels.rms = self
+                .sum_squares
+                .iter()
+                .map(|sum| (sum / frames).sqrt().min(1.0))
+                .collect();
+            levels.updated = Some(std::time::Instant::now());
+        }
+        self.peak.fill(0.0);
+        self.sum_squares.fill(0.0);
+        self.counted = 0;
+    }
+}
+
+impl<S> Iterator for Meter<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next()?;
+        if !self.peak.is_empty() {
+            let channel = self.channel % self.peak.len();
+            self.peak[channel] = self.peak[channel].max(sample.abs());
+            self.sum_squares[channel] += sample * sample;
+            self.channel = channel + 1;
+            self.counted += 1;
+
+            let window = self.inner.sample_rate() as f32 * METER_WINDOW.as_secs_f32();
+            if self.channel == self.peak.len() && self.counted >= window as usize * self.peak.len()
+            {
+                self.publish();
+            }
+        }
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Meter<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+/// How far back `ClipMeter` looks when reporting clipping.
+const CLIP_WINDOW: Duration = Duration::from_secs(1);
+/// Where the limiter's curve starts to bend. Anything quieter passes untouched.
+const LIMITER_KNEE: f32 = 0.8;
+
+/// Samples that reached full scale once the volume was applied, shared between the playing
+/// sources and the daemon.
+#[derive(Default)]
+pub struct ClipMeter {
+    // The volume rodio applies after the sources, as f32 bits, so they know what the device
+    // will be sent.
+    gain: AtomicU32,
+    // How many samples clipped in each metering window that had any, oldest first.
+    recent: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl ClipMeter {
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    /// Samples that clipped in the last `CLIP_WINDOW`.
+    pub fn clipped(&self) -> u64 {
+        let mut recent = self.recent.lock().unwrap();
+        prune(&mut recent);
+        recent.iter().map(|(_, count)| count).sum()
+    }
+
+    fn record(&self, count: u64) {
+        if let Ok(mut recent) = self.recent.try_lock() {
+            prune(&mut recent);
+            recent.push_back((Instant::now(), count));
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<(Instant, u64)>) {
+    while recent
+        .front()
+        .is_some_and(|(at, _)| at.elapsed() >= CLIP_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+/// Counts the samples that will reach or pass full scale once the volume is applied, and
+/// with `limit` bends them back under it with a soft knee instead of letting the device clip
+/// them hard.
+///
+/// Counts go to the shared `ClipMeter` once per metering window, and the volume is picked
+/// up from it at the same time.
+pub struct ClipGuard<S> {
+    inner: S,
+    meter: Arc<ClipMeter>,
+    limit: bool,
+    gain: f32,
+    clipped: u64,
+    // Samples seen in the current window, across all channels.
+    counted: usize,
+}
+
+impl<S> ClipGuard<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, meter: Arc<ClipMeter>, limit: bool) -> Self {
+        let gain = meter.gain();
+        ClipGuard {
+            inner,
+            meter,
+            limit,
+            gain,
+            clipped: 0,
+            counted: 0,
+        }
+    }
+}
+
+/// Leaves samples below the knee alone and eases louder ones towards full scale, which they
+/// never quite reach.
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= LIMITER_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - LIMITER_KNEE;
+    (LIMITER_KNEE + headroom * ((level - LIMITER_KNEE) / headroom).tanh()).copysign(sample)
+}
+
+impl<S> Iterator for ClipGuard<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        let mut sample = self.inner.next()?;
+        let output = sample * self.gain;
+        if output.abs() >= 1.0 {
+            self.clipped += 1;
+        }
+        if self.limit && self.gain > 0.0 {
+            sample = soft_clip(output) / self.gain;
+        }
+
+        self.counted += 1;
+        let window = self.inner.sample_rate() as f32 * METER_WINDOW.as_secs_f32();
+        if self.counted >= window as usize * self.inner.channels() as usize {
+            if self.clipped > 0 {
+                self.meter.record(self.clipped);
+            }
+            self.gain = self.meter.gain();
+            self.clipped = 0;
+            self.counted = 0;
+        }
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for ClipGuard<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+/// Shifts a source between the left and right speakers.
+///
+/// The balance is shared so the player can change it while the source is playing: -1.0 is
+/// full left, 0.0 centre, and 1.0 full right. The side being moved away from is turned down
+/// and the other is left at full level. Mono sources are played as stereo so they can be
+/// panned too, and any channels beyond the first two are passed through unchanged.
+pub struct Balance<S> {
+    inner: S,
+    balance: Arc<AtomicU32>,
+    mono: bool,
+    // The right-hand copy of the last mono sample.
+    pending: Option<f32>,
+    channel: usize,
+    gains: (f32, f32),
+}
+
+impl<S> Balance<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, balance: Arc<AtomicU32>) -> Self {
+        let mono = inner.channels() == 1;
+        Balance {
+            inner,
+            balance,
+            mono,
+            pending: None,
+            channel: 0,
+            gains: (1.0, 1.0),
+        }
+    }
+
+    /// Reads the shared balance once per frame, so both sides of a frame use the same value.
+    fn update_gains(&mut self) {
+        let balance = f32::from_bits(self.balance.load(Ordering::Relaxed)).clamp(-1.0, 1.0);
+        self.gains = ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0));
+    }
+}
+
+impl<S> Iterator for Balance<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if self.mono {
+            if let Some(sample) = self.pending.take() {
+                return Some(sample * self.gains.1);
+            }
+            let sample = self.inner.next()?;
+            self.update_gains();
+            self.pending = Some(sample);
+            return Some(sample * self.gains.0);
+        }
+
+        let sample = self.inner.next()?;
+        let channels = self.inner.channels().max(1) as usize;
+        let channel = self.channel % channels;
+        self.channel = (channel + 1) % channels;
+        if channel == 0 {
+            self.update_gains();
+        }
+        Some(match channel {
+            0 if channels > 1 => sample * self.gains.0,
+            1 => sample * self.gains.1,
+            _ => sample,
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        match self.mono {
+            true => (lower * 2, upper.map(|upper| upper * 2)),
+            false => (lower, upper),
+        }
+    }
+}
+
+impl<S> Source for Balance<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        let len = self.inner.current_span_len()?;
+        match self.mono {
+            true => Some(len * 2 + self.pending.is_some() as usize),
+            false => Some(len),
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        if self.mono { 2 } else { self.inner.channels() }
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.pending = None;
+        self.channel = 0;
+        self.inner.try_seek(pos)
+    }
+}
+
+/// Sums every channel of a source into one, for playing through a single speaker.
+///
+/// Each frame is averaged rather than added, so a track that's loud on both sides doesn't
+/// clip. Sources that are already mono pass through unchanged. The channel count is taken
+/// when the source is wrapped, like `Balance`.
+pub struct Mono<S> {
+    inner: S,
+    channels: usize,
+}
+
+impl<S> Mono<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        Mono { inner, channels }
+    }
+}
+
+impl<S> Iterator for Mono<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        let mut sum = self.inner.next()?;
+        for _ in 1..self.channels {
+            // A frame cut short at the end of the track is averaged as if it were silent.
+            sum += self.inner.next().unwrap_or_default();
+        }
+        Some(sum / self.channels as f32)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        (
+            lower / self.channels,
+            upper.map(|upper| upper.div_ceil(self.channels)),
+        )
+    }
+}
+
+impl<S> Source for Mono<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        let len = self.inner.current_span_len()?;
+        Some(len.div_ceil(self.channels))
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        1
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+/// How much of each channel the other one hears, and the corner of the low-pass it goes
+/// through on the way. These are the usual Bauer-style defaults: about what reaches the far
+/// ear from a pair of speakers.
+const CROSSFEED_LEVEL_DB: f32 = -4.5;
+const CROSSFEED_CUTOFF: f32 = 700.0;
+/// Roughly the extra time sound takes to reach the far ear.
+const CROSSFEED_DELAY: Duration = Duration::from_micros(300);
+
+/// Mixes a little of each stereo channel into the other, low-passed and slightly delayed,
+/// so hard-panned recordings sound less tiring on headphones.
+///
+/// The mix is scaled back down so a centred bass note comes out no louder than it went in.
+/// Anything other than two channels passes through unchanged. The format is taken when the
+/// source is wrapped, like `Balance`.
+pub struct Crossfeed<S> {
+    inner: S,
+    stereo: bool,
+    level: f32,
+    // Low-pass coefficient and each channel's filter state.
+    alpha: f32,
+    lowpassed: [f32; 2],
+    // The low-passed frames on their way to the other side, oldest first.
+    delayed: VecDeque<[f32; 2]>,
+    // The right-hand sample of the frame whose left one was just returned.
+    pending: Option<f32>,
+}
+
+impl<S> Crossfeed<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S) -> Self {
+        let stereo = inner.channels() == 2;
+        let sample_rate = inner.sample_rate().max(1) as f32;
+        let delay = (sample_rate * CROSSFEED_DELAY.as_secs_f32())
+            .round()
+            .max(1.0) as usize;
+        Crossfeed {
+            inner,
+            stereo,
+            level: 10f32.powf(CROSSFEED_LEVEL_DB / 20.0),
+            alpha: 1.0 - (-std::f32::consts::TAU * CROSSFEED_CUTOFF / sample_rate).exp(),
+            lowpassed: [0.0; 2],
+            delayed: VecDeque::from(vec![[0.0; 2]; delay]),
+            pending: None,
+        }
+    }
+}
+
+impl<S> Iterator for Crossfeed<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if !self.stereo {
+            return self.inner.next();
+        }
+        if let Some(right) = self.pending.take() {
+            return Some(right);
+        }
+        let left = self.inner.next()?;
+        // A frame cut short at the end of the track is played as it is.
+        let Some(right) = self.inner.next() else {
+            return Some(left);
+        };
+        for (state, sample) in self.lowpassed.iter_mut().zip([left, right]) {
+            *state += self.alpha * (sample - *state);
+        }
+        self.delayed.push_back(self.lowpassed);
+        let [from_left, from_right] = self.delayed.pop_front().unwrap_or_default();
+        let scale = 1.0 / (1.0 + self.level);
+        self.pending = Some((right + self.level * from_left) * scale);
+        Some((left + self.level * from_right) * scale)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        let pending = self.pending.is_some() as usize;
+        (lower + pending, upper.map(|upper| upper + pending))
+    }
+}
+
+impl<S> Source for Crossfeed<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        let len = self.inner.current_span_len()?;
+        Some(len + self.pending.is_some() as usize)
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.pending = None;
+        self.lowpassed = [0.0; 2];
+        self.delayed.iter_mut().for_each(|frame| *frame = [0.0; 2]);
+        Ok(())
+    }
+}
+
+/// The equalizer's bands, by name, with the frequency in Hz each one is centred on. The low
+/// and high bands are shelves, so they also lift or cut everything beyond their frequency.
+pub const EQ_BANDS: [(&str, f32); 3] = [("low", 250.0), ("mid", 1000.0), ("high", 4000.0)];
+
+/// The equalizer's gain per band in dB, shared so the player can change it while sources
+/// are playing.
+#[derive(Default)]
+pub struct EqGains {
+    gains: [AtomicU32; EQ_BANDS.len()],
+    // Bumped on every change, so sources only recompute their filters when they need to.
+    version: AtomicU64,
+}
+
+impl EqGains {
+    pub fn get(&self) -> [f32; EQ_BANDS.len()] {
+        self.gains
+            .each_ref()
+            .map(|gain| f32::from_bits(gain.load(Ordering::Relaxed)))
+    }
+
+    pub fn set(&self, gains: [f32; EQ_BANDS.len()]) {
+        for (shared, gain) in self.gains.iter().zip(gains) {
+            shared.store(gain.to_bits(), Ordering::Relaxed);
+        }
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// One second-order filter's coefficients, divided through by `a0`. The formulas are the
+/// usual ones from Robert Bristow-Johnson's audio EQ cookbook.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    /// The filter for band `band` of `EQ_BANDS` at `gain_db`.
+    fn band(band: usize, sample_rate: u32, gain_db: f32) -> Self {
+        // Kept under the Nyquist frequency, which low sample rates would otherwise put
+        // the high band on.
+        let frequency = EQ_BANDS[band].1.min(sample_rate as f32 * 0.45);
+        let w0 = std::f32::consts::TAU * frequency / sample_rate.max(1) as f32;
+        let (sin, cos) = w0.sin_cos();
+        let a = 10f32.powf(gain_db / 40.0);
+        if band == 1 {
+            // A peak about an octave and a half wide.
+            let alpha = sin / (2.0 * 0.7);
+            return Biquad::normalized(
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            );
+        }
+        // Shelves with a slope of 1, as steep as they go without overshooting.
+        let alpha = sin / 2.0 * std::f32::consts::SQRT_2;
+        let root = 2.0 * a.sqrt() * alpha;
+        // The high shelf is the low shelf with the sign of every `cos` term flipped.
+        let cos = if band == 0 { cos } else { -cos };
+        let sign = if band == 0 { 1.0 } else { -1.0 };
+        Biquad::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root),
+                sign * 2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + root,
+                sign * -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    fn normalized(b: [f32; 3], a: [f32; 3]) -> Self {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+
+    /// Filters one sample, in transposed direct form II with `state` as the two delays.
+    #[inline]
+    fn process(&self, state: &mut [f32; 2], x: f32) -> f32 {
+        let y = self.b0 * x + state[0];
+        state[0] = self.b1 * x - self.a1 * y + state[1];
+        state[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Boosts or cuts bass, mids, and treble with a chain of biquad filters, one per band.
+///
+/// The gains are shared so the player can change them while the source is playing, and a
+/// flat setting passes samples straight through. The format is taken when the source is
+/// wrapped, like `Balance`.
+pub struct Equalizer<S> {
+    inner: S,
+    gains: Arc<EqGains>,
+    // The version of the gains the filters were worked out for.
+    version: Option<u64>,
+    channels: usize,
+    sample_rate: rodio::SampleRate,
+    channel: usize,
+    filters: [Biquad; EQ_BANDS.len()],
+    // Every band's delays, for each channel.
+    state: Vec<[[f32; 2]; EQ_BANDS.len()]>,
+    active: bool,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source,
+{
+    pub fn new(inner: S, gains: Arc<EqGains>) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let sample_rate = inner.sample_rate();
+        Equalizer {
+            inner,
+            gains,
+            version: None,
+            channels,
+            sample_rate,
+            channel: 0,
+            filters: [Biquad::default(); EQ_BANDS.len()],
+            state: vec![Default::default(); channels],
+            active: false,
+        }
+    }
+
+    /// Picks up new gains between frames, so every channel of a frame is filtered alike.
+    fn update_filters(&mut self) {
+        let version = self.gains.version.load(Ordering::Relaxed);
+        if self.version == Some(version) {
+            return;
+        }
+        self.version = Some(version);
+        let gains = self.gains.get();
+        let was_active = self.active;
+        self.active = gains.iter().any(|gain| *gain != 0.0);
+        // Delays left from before a bypass belong to long-gone audio.
+        if self.active && !was_active {
+            self.state.fill(Default::default());
+        }
+        for (band, filter) in self.filters.iter_mut().enumerate() {
+            *filter = Biquad::band(band, self.sample_rate, gains[band]);
+        }
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.channels;
+        if channel == 0 {
+            self.update_filters();
+        }
+        if !self.active {
+            return Some(sample);
+        }
+        let state = &mut self.state[channel];
+        Some(
+            self.filters
+                .iter()
+                .zip(state.iter_mut())
+                .fold(sample, |sample, (filter, state)| {
+                    filter.process(state, sample)
+                }),
+        )
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source,
+{
+    #[inline]
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> rodio::ChannelCount {
+        self.channels as rodio::ChannelCount
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.sample_rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.channel = 0;
+        self.state.fill(Default::default());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// A stereo buffer counting up from `first`, so a lost or repeated sample shows.
+    fn ramp(first: usize, len: usize) -> SamplesBuffer {
+        let samples = (first..first + len).map(|n| n as f32).collect::<Vec<_>>();
+        SamplesBuffer::new(2, 1000, samples)
+    }
+
+    #[test]
+    fn tracks_join_cleanly_through_the_stretcher() {
+        let ratio = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+        let track = |first| {
+            let stretched = TimeStretch::new(ramp(first, 1000), Arc::clone(&ratio));
+            Cancellable::new(stretched, Arc::new(AtomicBool::new(false)))
+        };
+
+        // Two halves of one ramp, played back to back the way the sink would.
+        let played: Vec<f32> = track(1).chain(track(1001)).collect();
+        let expected: Vec<f32> = (1..2001).map(|n| n as f32).collect();
+        assert_eq!(played, expected);
+    }
+
+    #[test]
+    fn tracks_join_cleanly_through_prefetch_and_gap() {
+        let gap = Duration::from_millis(10);
+        let (first, ahead) = Prefetch::new(ramp(1, 1000));
+        let mut first = Gap::new(first, gap);
+        let (second, _) = Prefetch::new(ramp(2001, 1000));
+        let second = Gap::new(second, gap);
+
+        // Part of the first track is read before the prefetch catches up with the rest.
+        let mut played: Vec<f32> = first.by_ref().take(100).collect();
+        ahead.fill(Duration::from_millis(200), &AtomicBool::new(false));
+        played.extend(first.chain(second));
+
+        let silence = [0.0; 20];
+        let expected: Vec<f32> = (1..1001)
+            .map(|n| n as f32)
+            .chain(silence)
+            .chain((2001..3001).map(|n| n as f32))
+            .chain(silence)
+            .collect();
+        assert_eq!(played, expected);
+    }
+}
@@ -0,0 +1,275 @@
+use std::borrow::Cow;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, sync_channel};
+use std::time::Duration;
+
+/// Size of each read from the network, until changed with `tune`.
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+/// How far the download thread may get ahead of playback, so short network hiccups don't
+/// stall the audio.
+pub const DEFAULT_READ_AHEAD: usize = 1024 * 1024;
+/// Bounds `tune` accepts for either size.
+pub const MIN_BUFFER: usize = 1024;
+pub const MAX_BUFFER: usize = 64 * 1024 * 1024;
+
+// The sizes streams opened from now on use. Streams already playing keep theirs.
+static CHUNK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK_SIZE);
+static READ_AHEAD: AtomicUsize = AtomicUsize::new(DEFAULT_READ_AHEAD);
+/// Bytes kept behind the read position. Format probing rewinds a little after reading the
+/// header, which is the only seeking a live stream can support.
+const REWIND_LIMIT: usize = 512 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The read size and read-ahead, in bytes, that the next stream opened will use.
+pub fn buffers() -> (usize, usize) {
+    (
+        CHUNK_SIZE.load(Ordering::Relaxed),
+        READ_AHEAD.load(Ordering::Relaxed),
+    )
+}
+
+/// Changes the sizes for streams opened from now on. The caller checks them against
+/// `MIN_BUFFER` and `MAX_BUFFER`.
+pub fn set_buffers(chunk_size: usize, read_ahead: usize) {
+    CHUNK_SIZE.store(chunk_size, Ordering::Relaxed);
+    READ_AHEAD.store(read_ahead, Ordering::Relaxed);
+}
+
+pub fn is_url(track: &str) -> bool {
+    track.starts_with("http://") || track.starts_with("https://")
+}
+
+/// Turns a `file://` URI, as file managers hand them out, into the local path it names.
+/// Anything else is returned unchanged. Only files on this machine can be played, so a URI
+/// naming another host is refused.
+pub fn local_path(track: &str) -> Result<Cow<'_, str>, String> {
+    let Some(rest) = track
+        .get(..5)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("file:"))
+        .map(|_| &track[5..])
+    else {
+        return Ok(Cow::Borrowed(track));
+    };
+    let path = match rest.strip_prefix("//") {
+        Some(authority) => {
+            let (host, path) = authority.split_at(authority.find('/').unwrap_or(authority.len()));
+            if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+                return Err(format!(
+                    "{} is on another machine ({}); only local files can be played",
+                    track, host
+                ));
+            }
+            path
+        }
+        // `file:/path` is the short form of the same thing.
+        None if rest.starts_with('/') => rest,
+        None => return Ok(Cow::Borrowed(track)),
+    };
+    if path.is_empty() {
+        return Err(format!("{} doesn't name a file", track));
+    }
+    percent_decode(path)
+        .map(Cow::Owned)
+        .ok_or_else(|| format!("{} isn't a valid file URI", track))
+}
+
+/// Replaces each `%XX` escape with the byte it stands for. `None` for a malformed escape or
+/// a result that isn't UTF-8.
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = rest
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).ok()
+}
+
+struct Headers {
+    content_length: Option<u64>,
+    content_type: Option<String>,
+    name: Option<String>,
+}
+
+fn connect(url: &str) -> Result<reqwest::blocking::Response, String> {
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        // Radio streams never end, so only the connection is timed out.
+        .timeout(None)
+        .build()
+        .map_err(|e| format!("Failed to set up HTTP client: {}", e))?;
+    client
+        .get(url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to connect to {}: {}", url, e))
+}
+
+/// An HTTP response body read by a background thread.
+///
+/// The decoder needs `Read + Seek`, but a stream can only be read forwards, so seeks are
+/// limited to the bytes still held in memory.
+pub struct HttpStream {
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    buffer: Vec<u8>,
+    // Stream offset of `buffer[0]`.
+    base: u64,
+    position: u64,
+    finished: bool,
+    pub content_length: Option<u64>,
+    pub content_type: Option<String>,
+    // Station name sent by Icecast/SHOUTcast servers.
+    pub name: Option<String>,
+}
+
+impl HttpStream {
+    /// Connects to `url` and starts downloading in the background. Fails if the server
+    /// can't be reached or doesn't answer with a success status.
+    pub fn open(url: &str) -> Result<HttpStream, String> {
+        // reqwest's blocking client can't be used from async code, so the whole request
+        // lives on its own thread and the headers are sent back first.
+        let (headers_sender, headers) = sync_channel(1);
+        let (chunk_size, read_ahead) = buffers();
+        let (sender, receiver) = sync_channel((read_ahead / chunk_size).max(1));
+        let url = url.to_string();
+        std::thread::spawn(move || {
+            let mut response = match connect(&url) {
+                Ok(response) => response,
+                Err(e) => {
+                    let _ = headers_sender.send(Err(e));
+                    return;
+                }
+            };
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string())
+            };
+            let info = Headers {
+                content_length: response.content_length(),
+                content_type: header("content-type"),
+                name: header("icy-name").filter(|name| !name.trim().is_empty()),
+            };
+            if headers_sender.send(Ok(info)).is_err() {
+                return;
+            }
+
+            loop {
+                let mut chunk = vec![0; chunk_size];
+                match response.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        // The player dropped the stream.
+                        if sender.send(Ok(chunk)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                }
+            }
+        });
+
+        let headers = headers
+            .recv()
+            .map_err(|_| "HTTP request thread stopped unexpectedly".to_string())??;
+        Ok(HttpStream {
+            chunks: Mutex::new(receiver),
+            buffer: Vec::new(),
+            base: 0,
+            position: 0,
+            finished: false,
+            content_length: headers.content_length,
+            content_type: headers.content_type,
+            name: headers.name,
+        })
+    }
+
+    /// Waits for the next chunk from the download thread. Returns false at the end.
+    fn fetch(&mut self) -> io::Result<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+        match self.chunks.get_mut().unwrap().recv() {
+            Ok(Ok(chunk)) => {
+                self.buffer.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            Ok(Err(e)) => {
+                self.finished = true;
+                Err(e)
+            }
+            Err(_) => {
+                self.finished = true;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Forgets bytes that are too far behind the read position to be rewound to.
+    fn trim(&mut self) {
+        let behind = (self.position - self.base) as usize;
+        if behind > REWIND_LIMIT * 2 {
+            let excess = behind - REWIND_LIMIT;
+            self.buffer.drain(..excess);
+            self.base += excess as u64;
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.base + self.buffer.len() as u64 {
+            if !self.fetch()? {
+                return Ok(0);
+            }
+        }
+        let start = (self.position - self.base) as usize;
+        let n = buf.len().min(self.buffer.len() - start);
+        buf[..n].copy_from_slice(&self.buffer[start..start + n]);
+        self.position += n as u64;
+        self.trim();
+        Ok(n)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self
+                .content_length
+                .and_then(|length| length.checked_add_signed(delta)),
+        };
+        let Some(target) = target.filter(|target| *target >= self.base) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek this far back in a stream",
+            ));
+        };
+        // Seeking forwards just means reading ahead.
+        while target > self.base + self.buffer.len() as u64 {
+            if !self.fetch()? {
+                break;
+            }
+        }
+        self.position = target.min(self.base + self.buffer.len() as u64);
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,93 @@
+use lofty::picture::PictureType;
+use lofty::prelude::*;
+use std::path::Path;
+
+/// The tag fields we show for a track. Any of them may be missing, and untagged files
+/// yield all `None`.
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub replay_gain: ReplayGain,
+}
+
+/// ReplayGain adjustments in dB, with the peak sample levels they were computed against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Reads ID3/Vorbis/MP4/etc. tags from `path`. Unreadable files are treated as untagged
+/// rather than as an error, since the audio itself may still decode fine.
+pub fn read_tags(path: &Path) -> Tags {
+    let Ok(tagged_file) = lofty::read_from_path(path) else {
+        return Tags::default();
+    };
+    let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    else {
+        return Tags::default();
+    };
+
+    let field = |value: Option<std::borrow::Cow<'_, str>>| {
+        value
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    // Values look like "-6.54 dB" for gains and "0.988525" for peaks.
+    let number = |key: ItemKey| {
+        let value = tag.get_string(key)?.trim();
+        let value = value
+            .strip_suffix("dB")
+            .or_else(|| value.strip_suffix("db"))
+            .unwrap_or(value);
+        value.trim().parse::<f32>().ok().filter(|n| n.is_finite())
+    };
+    Tags {
+        title: field(tag.title()),
+        artist: field(tag.artist()),
+        album: field(tag.album()),
+        replay_gain: ReplayGain {
+            track_gain: number(ItemKey::ReplayGainTrackGain),
+            track_peak: number(ItemKey::ReplayGainTrackPeak),
+            album_gain: number(ItemKey::ReplayGainAlbumGain),
+            album_peak: number(ItemKey::ReplayGainAlbumPeak),
+        },
+    }
+}
+
+/// Embedded cover art for `path` as raw image bytes and a file extension, preferring the
+/// front cover over any other picture.
+pub fn read_cover(path: &Path) -> Option<(Vec<u8>, String)> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())?;
+    let picture = tag
+        .get_picture_type(PictureType::CoverFront)
+        .or_else(|| tag.pictures().first())?;
+    let ext = picture
+        .mime_type()
+        .and_then(|mime| mime.ext())
+        .unwrap_or("jpg");
+    Some((picture.data().to_vec(), ext.to_string()))
+}
+
+impl Tags {
+    /// "Artist – Title" when both are known, otherwise the title, otherwise the file name.
+    pub fn display_name(&self, path: &Path) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} – {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+        }
+    }
+}
@@ -0,0 +1,815 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::*;
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// A directory under the system temp dir that's removed again when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "udsaudio-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Stands in for the sound card: pulls samples out of a mixer at the rate a real output
+/// would, so tracks take as long to play as they would on a device.
+struct NullOutput {
+    mixer: rodio::mixer::Mixer,
+    stop: Arc<AtomicBool>,
+}
+
+impl NullOutput {
+    fn new() -> Self {
+        let (mixer, mut source) = rodio::mixer::mixer(2, SAMPLE_RATE);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut pulled: u64 = 0;
+            while !stopped.load(Ordering::Relaxed) {
+                let due = (started.elapsed().as_secs_f64() * f64::from(SAMPLE_RATE) * 2.0) as u64;
+                for _ in pulled..due {
+                    source.next();
+                }
+                pulled = pulled.max(due);
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        NullOutput { mixer, stop }
+    }
+}
+
+impl Drop for NullOutput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A daemon serving a socket in its own temp dir, playing into a [`NullOutput`].
+struct TestDaemon {
+    dir: TempDir,
+    socket: PathBuf,
+    player: Arc<Mutex<Player>>,
+    server: JoinHandle<()>,
+    _output: NullOutput,
+}
+
+impl TestDaemon {
+    /// Starts a daemon with the given `daemon` options. Fades are off unless asked for, so
+    /// commands take effect straight away.
+    async fn start(options: &[&str]) -> Self {
+        let dir = TempDir::new();
+        let socket = dir.path().join("test.sock");
+        let fade: &[&str] = match options.contains(&"--fade-ms") {
+            true => &[],
+            false => &["--fade-ms", "0"],
+        };
+        let args = ["udsaudio", "daemon"]
+            .iter()
+            .chain(fade)
+            .chain(options)
+            .copied();
+        let Commands::Daemon(options) = Args::try_parse_from(args).unwrap().command else {
+            unreachable!();
+        };
+        let output = NullOutput::new();
+        let player = Arc::new(Mutex::new(Player::new(
+            &output.mixer,
+            (2, SAMPLE_RATE),
+            &options,
+            json!({}),
+        )));
+        let listener = socket::Endpoint::Path(socket.clone()).bind().await.unwrap();
+        let server = tokio::spawn(serve(
+            listener,
+            None,
+            Arc::clone(&player),
+            Duration::from_secs(options.request_timeout),
+            None,
+        ));
+        TestDaemon {
+            dir,
+            socket,
+            player,
+            server,
+            _output: output,
+        }
+    }
+
+    fn client(&self) -> Client {
+        Client {
+            endpoint: socket::Endpoint::Path(self.socket.clone()),
+            token: None,
+            retries: 0,
+            retry_delay: Duration::ZERO,
+            session: None,
+        }
+    }
+
+    async fn send(&self, cmd: Value) -> Value {
+        send_command(&self.client(), cmd).await
+    }
+
+    /// Writes a WAV of `secs` seconds into the daemon's temp dir.
+    fn wav(&self, name: &str, secs: f32) -> String {
+        let path = self.dir.path().join(name);
+        write_wav(&path, secs);
+        path.display().to_string()
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A mono 16-bit tone, just long enough to be heard for `secs`.
+fn write_wav(path: &Path, secs: f32) {
+    let frames = (secs * SAMPLE_RATE as f32) as u32;
+    let data_len = frames * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for frame in 0..frames {
+        let phase = frame as f32 * 440.0 / SAMPLE_RATE as f32;
+        let sample = ((phase * std::f32::consts::TAU).sin() * 8000.0) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, wav).unwrap();
+}
+
+#[tokio::test]
+async fn daemon_answers_on_its_socket() {
+    let daemon = TestDaemon::start(&[]).await;
+    let socket = daemon.socket.display().to_string();
+    let args = Args::try_parse_from(["udsaudio", "--socket", &socket, "status"]).unwrap();
+    assert_eq!(resolve_socket_path(args.socket), daemon.socket);
+
+    let status = daemon.send(Actions::Status.request(json!({}))).await;
+    assert_eq!(status["status"], true, "{}", status);
+    assert_eq!(status["is_playing"], false, "{}", status);
+    assert_eq!(status["queue_length"], 0, "{}", status);
+
+    let track = daemon.wav("tone.wav", 2.0);
+    let played = daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+    assert_eq!(played["status"], true, "{}", played);
+    assert_eq!(daemon.player.lock().await.queue.len(), 1);
+
+    let status = daemon.send(Actions::Status.request(json!({}))).await;
+    assert_eq!(status["is_playing"], true, "{}", status);
+    assert_eq!(status["current_path"], track, "{}", status);
+}
+
+#[tokio::test]
+async fn client_that_hangs_up_silently_leaves_the_daemon_serving() {
+    let daemon = TestDaemon::start(&[]).await;
+
+    let silent = UnixStream::connect(&daemon.socket).await.unwrap();
+    drop(silent);
+
+    let volume = daemon.send(Actions::Volume.request(json!({}))).await;
+    assert_eq!(volume["status"], true, "{}", volume);
+}
+
+#[tokio::test]
+async fn long_paths_arrive_intact() {
+    let daemon = TestDaemon::start(&[]).await;
+    let mut dir = daemon.dir.path().to_path_buf();
+    while dir.as_os_str().len() < 4000 {
+        dir.push("d".repeat(200));
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    let track = dir.join("tone.wav");
+    write_wav(&track, 1.0);
+    let track = track.display().to_string();
+    assert!(track.len() > 4096 - 100);
+
+    let played = daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+    assert_eq!(played["status"], true, "{}", played);
+
+    let current = daemon.send(Actions::Current.request(json!({}))).await;
+    assert_eq!(current["path"], track, "{}", current);
+}
+
+#[tokio::test]
+async fn overlong_requests_are_refused() {
+    let daemon = TestDaemon::start(&[]).await;
+    let track = "x".repeat(MAX_REQUEST_LEN as usize);
+    let request = format!("{}\n", Actions::Play.request(json!({"track": track})));
+
+    let mut stream = UnixStream::connect(&daemon.socket).await.unwrap();
+    let (reader, mut writer) = stream.split();
+    // The daemon stops reading partway, so the write may fail once it hangs up.
+    let _ = writer.write_all(request.as_bytes()).await;
+    let mut line = String::new();
+    tokio::io::BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(response["status"], false, "{}", response);
+    assert!(
+        response["message"]
+            .as_str()
+            .unwrap()
+            .contains("longer than"),
+        "{}",
+        response
+    );
+
+    let volume = daemon.send(Actions::Volume.request(json!({}))).await;
+    assert_eq!(volume["status"], true, "{}", volume);
+}
+
+/// Reads until the daemon closes `stream`, returning what it sent.
+async fn read_to_close(stream: &mut UnixStream) -> String {
+    let mut received = String::new();
+    stream.read_to_string(&mut received).await.unwrap();
+    received
+}
+
+#[tokio::test]
+async fn partial_requests_are_dropped_after_the_timeout() {
+    let daemon = TestDaemon::start(&["--request-timeout", "1"]).await;
+
+    let mut stream = UnixStream::connect(&daemon.socket).await.unwrap();
+    stream.write_all(b"{\"action\": \"sta").await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_secs(3), read_to_close(&mut stream)).await;
+    assert_eq!(closed.expect("the daemon kept the connection open"), "");
+}
+
+#[tokio::test]
+async fn partial_requests_in_a_session_are_dropped_after_the_timeout() {
+    let daemon = TestDaemon::start(&["--request-timeout", "1"]).await;
+
+    let mut stream = UnixStream::connect(&daemon.socket).await.unwrap();
+    let session = format!("{}\n", Actions::Session.request(json!({})));
+    stream.write_all(session.as_bytes()).await.unwrap();
+    // Idling between requests is allowed.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    stream.write_all(b"{\"action\": \"sta").await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_secs(3), read_to_close(&mut stream)).await;
+    let received = closed.expect("the daemon kept the session open");
+    assert_eq!(received.lines().count(), 1, "{}", received);
+    assert_eq!(parse_response(&received)["status"], true, "{}", received);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_status_requests_all_get_answers() {
+    let daemon = Arc::new(TestDaemon::start(&[]).await);
+    let track = daemon.wav("tone.wav", 5.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+
+    let clients: Vec<_> = (0..32)
+        .map(|_| {
+            let daemon = Arc::clone(&daemon);
+            tokio::spawn(async move { daemon.send(Actions::Status.request(json!({}))).await })
+        })
+        .collect();
+    for client in clients {
+        let status = client.await.unwrap();
+        assert_eq!(status["status"], true, "{}", status);
+        assert_eq!(status["is_playing"], true, "{}", status);
+    }
+}
+
+#[tokio::test]
+async fn second_daemon_on_the_same_socket_fails() {
+    let daemon = TestDaemon::start(&[]).await;
+
+    let second = socket::Endpoint::Path(daemon.socket.clone()).bind().await;
+    let e = second.expect_err("a second daemon bound a live socket");
+    assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+
+    let pong = daemon.send(Actions::Ping.request(json!({}))).await;
+    assert_eq!(pong["status"], true, "{}", pong);
+}
+
+#[tokio::test]
+async fn ping_answers_while_the_player_is_busy() {
+    let daemon = TestDaemon::start(&[]).await;
+    let held = daemon.player.lock().await;
+    // Long enough for the queue to have come due for a sync at least once.
+    tokio::time::sleep(SYNC_INTERVAL * 2).await;
+
+    let ping = daemon.send(Actions::Ping.request(json!({})));
+    let pong = tokio::time::timeout(Duration::from_secs(2), ping)
+        .await
+        .expect("ping waited for the player");
+    assert_eq!(pong["status"], true, "{}", pong);
+    drop(held);
+}
+
+#[tokio::test]
+async fn sockets_that_dont_answer_ping_are_replaced() {
+    let dir = TempDir::new();
+    let path = dir.path().join("stale.sock");
+    let endpoint = socket::Endpoint::Path(path.clone());
+
+    // Left behind by a daemon that crashed: nothing accepts connections any more.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    drop(endpoint.bind().await.unwrap());
+
+    // Something that accepts connections but isn't a daemon.
+    std::fs::remove_file(&path).unwrap();
+    let _squatter = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    endpoint.bind().await.unwrap();
+}
+
+#[tokio::test]
+async fn client_tells_a_missing_daemon_from_a_stuck_one() {
+    let dir = TempDir::new();
+    let path = dir.path().join("test.sock");
+    let client = Client {
+        endpoint: socket::Endpoint::Path(path.clone()),
+        token: None,
+        retries: 0,
+        retry_delay: Duration::ZERO,
+        session: None,
+    };
+    let status = || send_command(&client, Actions::Status.request(json!({})));
+
+    let missing = status().await;
+    assert_eq!(missing["status"], false, "{}", missing);
+    assert!(
+        missing["message"].as_str().unwrap().contains("not running"),
+        "{}",
+        missing
+    );
+
+    // Accepts connections but never answers them.
+    let _stuck = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let stuck = status().await;
+    assert_eq!(stuck["status"], false, "{}", stuck);
+    assert!(
+        stuck["message"]
+            .as_str()
+            .unwrap()
+            .contains("not responding"),
+        "{}",
+        stuck
+    );
+}
+
+/// Polls `action` until its response satisfies `done`, failing the test after `limit`.
+async fn wait_for(
+    daemon: &TestDaemon,
+    action: Actions,
+    limit: Duration,
+    done: impl Fn(&Value) -> bool,
+) -> Value {
+    let deadline = Instant::now() + limit;
+    loop {
+        let response = daemon.send(action.request(json!({}))).await;
+        if done(&response) {
+            return response;
+        }
+        assert!(Instant::now() < deadline, "gave up waiting: {}", response);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn queued_tracks_play_in_order() {
+    let daemon = TestDaemon::start(&[]).await;
+    let first = daemon.wav("first.wav", 0.5);
+    let second = daemon.wav("second.wav", 0.5);
+    daemon
+        .send(Actions::Play.request(json!({"track": first})))
+        .await;
+    daemon
+        .send(Actions::Queue.request(json!({"track": second})))
+        .await;
+
+    let current = daemon.send(Actions::Current.request(json!({}))).await;
+    assert_eq!(current["path"], first, "{}", current);
+    assert_eq!(current["queue_length"], 2, "{}", current);
+
+    let current = wait_for(
+        &daemon,
+        Actions::Current,
+        Duration::from_secs(3),
+        |current| current["path"] != first,
+    )
+    .await;
+    assert_eq!(current["path"], second, "{}", current);
+    assert_eq!(current["queue_length"], 1, "{}", current);
+}
+
+#[tokio::test]
+async fn position_advances_during_playback() {
+    let daemon = TestDaemon::start(&[]).await;
+    let track = daemon.wav("tone.wav", 3.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let status = daemon.send(Actions::Status.request(json!({}))).await;
+    let position = status["position_secs"].as_f64().unwrap();
+    let duration = status["duration_secs"].as_f64().unwrap();
+    assert!((0.5..duration).contains(&position), "{}", status);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let later = daemon.send(Actions::Status.request(json!({}))).await;
+    assert!(
+        later["position_secs"].as_f64().unwrap() > position,
+        "{}",
+        later
+    );
+}
+
+#[tokio::test]
+async fn status_answers_while_pausing_fades_out() {
+    let daemon = TestDaemon::start(&["--fade-ms", "1000"]).await;
+    let track = daemon.wav("tone.wav", 10.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+
+    let pause = daemon.send(Actions::Pause.request(json!({})));
+    let paused = tokio::time::timeout(Duration::from_millis(500), pause)
+        .await
+        .expect("pause waited for the fade");
+    assert_eq!(paused["status"], true, "{}", paused);
+    let status = daemon.send(Actions::Status.request(json!({})));
+    let status = tokio::time::timeout(Duration::from_millis(500), status)
+        .await
+        .expect("status waited for the fade");
+    assert_eq!(status["state"], "paused", "{}", status);
+
+    // Once the fade is done the track actually stops moving.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let stopped = daemon.send(Actions::Status.request(json!({}))).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let later = daemon.send(Actions::Status.request(json!({}))).await;
+    assert_eq!(
+        stopped["position_secs"], later["position_secs"],
+        "{}",
+        later
+    );
+}
+
+#[tokio::test]
+async fn status_message_follows_the_state() {
+    let daemon = TestDaemon::start(&[]).await;
+    let track = daemon.wav("tone.wav", 5.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": track})))
+        .await;
+
+    for (action, state, message) in [
+        (Actions::Pause, "paused", "Paused tone.wav"),
+        (Actions::Stop, "stopped", "Stopped tone.wav"),
+        (Actions::Resume, "playing", "Playing tone.wav"),
+    ] {
+        daemon.send(action.request(json!({}))).await;
+        let status = daemon.send(Actions::Status.request(json!({}))).await;
+        assert_eq!(status["state"], state, "{}", status);
+        assert_eq!(status["message"], message, "{}", status);
+    }
+}
+
+#[tokio::test]
+async fn directory_tracks_are_opened_when_they_come_up() {
+    let daemon = TestDaemon::start(&[]).await;
+    let album = daemon.dir.path().join("album");
+    std::fs::create_dir(&album).unwrap();
+    for name in ["1.wav", "2.wav", "3.wav"] {
+        write_wav(&album.join(name), 0.3);
+    }
+    let played = daemon
+        .send(Actions::Play.request(json!({"track": album})))
+        .await;
+    assert_eq!(played["queued"], 3, "{}", played);
+
+    let last = album.join("3.wav").display().to_string();
+    wait_for(
+        &daemon,
+        Actions::Current,
+        Duration::from_secs(3),
+        |current| current["path"] == last,
+    )
+    .await;
+}
+
+#[test]
+fn same_seed_shuffles_the_same_way() {
+    let tracks: Vec<u32> = (0..50).collect();
+    let shuffled = |seed| {
+        let mut tracks = tracks.clone();
+        shuffle(&mut tracks, Some(seed));
+        tracks
+    };
+
+    assert_eq!(shuffled(7), shuffled(7));
+    assert_ne!(shuffled(7), tracks);
+    assert_ne!(shuffled(7), shuffled(8));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn huge_skip_counts_finish() {
+    let daemon = TestDaemon::start(&["--repeat", "all"]).await;
+    let tracks: Vec<_> = ["a.wav", "b.wav", "c.wav"]
+        .iter()
+        .map(|name| daemon.wav(name, 3.0))
+        .collect();
+    daemon
+        .send(Actions::Play.request(json!({"track": tracks})))
+        .await;
+
+    // A multiple of three laps, so it comes back around to the first track.
+    let skip = Actions::Skip.request(json!({"count": u64::MAX}));
+    let skipped = tokio::time::timeout(Duration::from_secs(5), daemon.send(skip))
+        .await
+        .expect("skipping under repeat all never finished");
+    assert_eq!(skipped["status"], true, "{}", skipped);
+    assert_eq!(skipped["current"]["path"], tracks[0], "{}", skipped);
+
+    daemon
+        .send(Actions::Repeat.request(json!({"mode": "off"})))
+        .await;
+    let skip = Actions::Skip.request(json!({"count": u64::MAX}));
+    let skipped = tokio::time::timeout(Duration::from_secs(5), daemon.send(skip))
+        .await
+        .expect("skipping past the end never finished");
+    assert_eq!(skipped["skipped"], 3, "{}", skipped);
+    assert!(skipped["current"].is_null(), "{}", skipped);
+}
+
+#[tokio::test]
+async fn cued_playlists_load_paused() {
+    let daemon = TestDaemon::start(&[]).await;
+    let tracks = [daemon.wav("a.wav", 2.0), daemon.wav("b.wav", 2.0)];
+    let list = daemon.dir.path().join("list.m3u");
+    std::fs::write(&list, tracks.join("\n")).unwrap();
+
+    let mut player = daemon.player.lock().await;
+    let loaded = probe_playlist(&list, player.room(Placement::Cue))
+        .and_then(|probed| enqueue_playlist(&mut player, &list, probed, Placement::Cue));
+    if let Err(e) = loaded {
+        panic!("{}", e);
+    }
+    drop(player);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let status = daemon.send(Actions::Status.request(json!({}))).await;
+    assert_eq!(status["state"], "paused", "{}", status);
+    assert_eq!(status["current_path"], tracks[0], "{}", status);
+    assert_eq!(status["queue_length"], 2, "{}", status);
+    assert_eq!(status["position_secs"], 0.0, "{}", status);
+}
+
+#[tokio::test]
+async fn playlist_streams_are_queued_without_connecting() {
+    let daemon = TestDaemon::start(&[]).await;
+    let track = daemon.wav("a.wav", 2.0);
+    // Nothing listens here, so connecting would fail the entry.
+    let url = "http://127.0.0.1:1/radio.mp3";
+    let list = daemon.dir.path().join("list.m3u");
+    std::fs::write(&list, format!("{}\n{}", track, url)).unwrap();
+
+    let queued = daemon
+        .send(Actions::Queue.request(json!({"track": list})))
+        .await;
+    assert_eq!(queued["status"], true, "{}", queued);
+    assert_eq!(queued["queued"], 2, "{}", queued);
+    assert_eq!(queued["failed"], json!([]), "{}", queued);
+}
+
+#[tokio::test]
+async fn play_replaces_what_is_playing() {
+    let daemon = TestDaemon::start(&[]).await;
+    let first = daemon.wav("first.wav", 3.0);
+    let second = daemon.wav("second.wav", 3.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": first})))
+        .await;
+    let played = daemon
+        .send(Actions::Play.request(json!({"track": second})))
+        .await;
+    assert_eq!(played["status"], true, "{}", played);
+
+    let current = daemon.send(Actions::Current.request(json!({}))).await;
+    assert_eq!(current["path"], second, "{}", current);
+    assert_eq!(current["queue_length"], 1, "{}", current);
+    let status = daemon.send(Actions::Status.request(json!({}))).await;
+    assert_eq!(status["is_playing"], true, "{}", status);
+}
+
+#[tokio::test]
+async fn player_goes_idle_when_the_queue_drains() {
+    let daemon = TestDaemon::start(&[]).await;
+    let track = daemon.wav("tone.wav", 0.5);
+    daemon
+        .send(Actions::Play.request(json!({"track": [track]})))
+        .await;
+
+    let status = wait_for(&daemon, Actions::Status, Duration::from_secs(3), |status| {
+        status["state"] != "playing"
+    })
+    .await;
+    assert_eq!(status["state"], "idle", "{}", status);
+    assert_eq!(status["queue_length"], 0, "{}", status);
+
+    let current = daemon.send(Actions::Current.request(json!({}))).await;
+    assert_eq!(current["status"], false, "{}", current);
+    assert_eq!(current["code"], "idle", "{}", current);
+}
+
+/// The `--history-log` lines written so far.
+fn history_lines(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(parse_response)
+        .collect()
+}
+
+#[tokio::test]
+async fn history_log_records_each_repeat() {
+    let log = TempDir::new();
+    let log = log.path().join("history.jsonl");
+    let daemon = TestDaemon::start(&["--history-log", log.to_str().unwrap()]).await;
+    daemon
+        .send(Actions::Repeat.request(json!({"mode": "one"})))
+        .await;
+    let track = daemon.wav("tone.wav", 0.4);
+    daemon
+        .send(Actions::Play.request(json!({"track": [track]})))
+        .await;
+
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let lines = history_lines(&log);
+    assert!(lines.len() >= 2, "{:?}", lines);
+    assert!(
+        lines.iter().all(|line| line["path"] == track),
+        "{:?}",
+        lines
+    );
+}
+
+#[tokio::test]
+async fn history_log_skips_pause_and_resume() {
+    let log = TempDir::new();
+    let log = log.path().join("history.jsonl");
+    let daemon = TestDaemon::start(&["--history-log", log.to_str().unwrap()]).await;
+    let track = daemon.wav("tone.wav", 3.0);
+    daemon
+        .send(Actions::Play.request(json!({"track": [track]})))
+        .await;
+
+    daemon.send(Actions::Pause.request(json!({}))).await;
+    daemon.send(Actions::Resume.request(json!({}))).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(history_lines(&log).len(), 1);
+}
+
+#[test]
+fn every_command_round_trips_through_the_protocol() {
+    let commands: &[&[&str]] = &[
+        &["play", "a.wav"],
+        &["pause"],
+        &["resume"],
+        &["toggle"],
+        &["restart"],
+        &["seek", "50%"],
+        &["forget", "a.wav"],
+        &["queue", "a.wav", "b.wav"],
+        &["history"],
+        &["history", "clear"],
+        &["playlist", "save", "evening"],
+        &["playlist", "load", "evening"],
+        &["playlist", "list"],
+        &["bookmark", "add", "chorus"],
+        &["bookmark", "list"],
+        &["bookmark", "jump", "chorus"],
+        &["insert", "1", "a.wav"],
+        &["clear"],
+        &["skip", "2"],
+        &["current"],
+        &["volume", "+0.1"],
+        &["stop"],
+        &["fadestop", "3"],
+        &["status"],
+        &["list"],
+        &["remove", "1"],
+        &["rename", "1", "Intro"],
+        &["save", "queue.m3u"],
+        &["repeat", "all"],
+        &["contains", "a.wav"],
+        &["dedup"],
+        &["sort", "name"],
+        &["shuffle"],
+        &["mute"],
+        &["unmute"],
+        &["prev"],
+        &["subscribe"],
+        &["speed", "1.5"],
+        &["balance", "0.5"],
+        &["eq"],
+        &["eq", "reset"],
+        &["jump", "2"],
+        &["move", "1", "2"],
+        &["swap", "1", "2"],
+        &["ping"],
+        &["shutdown"],
+        &["version"],
+        &["levels"],
+        &["config"],
+        &["tune"],
+    ];
+
+    let mut reached = Vec::new();
+    for words in commands {
+        let args = ["udsaudio"].iter().chain(*words);
+        let command = Args::try_parse_from(args)
+            .unwrap_or_else(|e| panic!("{:?}: {}", words, e))
+            .command;
+        let request = command
+            .request()
+            .unwrap_or_else(|| panic!("{:?} sends nothing", words))
+            .unwrap_or_else(|e| panic!("{:?}: {}", words, e));
+        let parsed = parse_command(&request.to_string())
+            .unwrap_or_else(|e| panic!("{:?} sent {}: {}", words, request, e));
+        assert_eq!(request["action"], parsed.action.name(), "{:?}", words);
+        for (field, _) in parsed.action.required_fields() {
+            assert!(!request[field].is_null(), "{:?} left out {}", words, field);
+        }
+        reached.push(parsed.action);
+    }
+
+    // Only the REPL's connection sends `session`, so no subcommand maps to it.
+    let session = Actions::Session.request(json!({}));
+    reached.push(parse_command(&session.to_string()).unwrap().action);
+
+    for action in Actions::ALL {
+        assert!(
+            reached.contains(action),
+            "no command sends {}",
+            action.name()
+        );
+    }
+}
+
+#[test]
+fn local_commands_send_nothing() {
+    let commands: &[&[&str]] = &[
+        &["daemon"],
+        &["repl"],
+        &["formats"],
+        &["devices"],
+        &["completions", "bash"],
+    ];
+    for words in commands {
+        let args = ["udsaudio"].iter().chain(*words);
+        let command = Args::try_parse_from(args).unwrap().command;
+        assert!(command.request().is_none(), "{:?}", words);
+    }
+}